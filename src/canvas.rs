@@ -1,65 +1,68 @@
-// # Stdout Raw handle
-extern crate kernel32;
-extern crate winapi;
-use std::os::windows::io::FromRawHandle;
-use std::fs::File;
-use std::mem;
-use std::sync::{Arc, Mutex};
-use std::{time::Duration, thread, time::Instant};
-use crossterm::style::{self, Color};
-use winapi::um::wincon::SetConsoleOutputCP;
-use crossterm::event::{Event, poll, read};
-use winapi::um::winnls::CP_UTF8;
+use crossterm::style::Color;
 
 // # Terminal handling
-use std::io::Write;
-use crossterm::{cursor, execute, queue, style::Stylize, terminal};
-use windows_capture::settings::ColorFormat;
+use std::io::{self, Write};
+use crossterm::{cursor, queue, terminal};
 
 // # FrameData
-use crate::frame::{FrameData, Size};
-use crate::processing::{ImageProcess, Kernel, Scaling};
+use crate::color::BlendSpace;
+use crate::frame::{ColorFormat, FrameData, Size};
+use crate::processing::{dither::Dither, filter::Filter, palette::Palette, ImageProcess, Scaling};
+use crate::render::{ansi::AnsiEncoder, block::Block, Renderer};
+use crate::source::FrameSource;
+use crate::term::{self, ColorDepth};
+
+// Screen height of a terminal cell over its width when the terminal doesn't report it
+pub const DEFAULT_CELL_ASPECT: f64 = 2.0;
+
+// Reductions past this factor average whole source areas, bilinear only samples four pixels and aliases
+const AREA_SCALE_FACTOR: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Fit {
+    // Whole frame visible, padded with the background color
+    Contain,
+    // Whole terminal covered, frame edges cropped
+    Cover,
+    // Frame squeezed to the terminal, ignoring aspect ratio
+    Stretch,
+}
 
-#[derive(Clone)]
 pub struct Canvas {
     frame_data: FrameData,
-    term_size: Arc<Mutex<Size>>,
+    term_size: Size,
+    renderer: Box<dyn Renderer>,
+    output: Vec<u8>,
+    fit: Fit,
+    scaling: Option<Scaling>,
+    cell_aspect: f64,
+    background: Color,
+    filters: Vec<Box<dyn Filter>>,
+    palette: Option<Palette>,
+    dither: Option<Dither>,
 }
 
 impl Canvas {
     pub fn new(color_format: ColorFormat) -> Self {
-        let multiplier = match color_format {
-            ColorFormat::Rgba16F => 8,
-            ColorFormat::Rgba8 => 4,
-            ColorFormat::Bgra8 => 4,
-        };
         let mut frame_data = FrameData::default();
-        frame_data.set_multiplier(multiplier);
+        frame_data.set_color_format(color_format);
+        term::enable_utf8();
         terminal::disable_raw_mode().unwrap();
         let ts = crossterm::terminal::size().unwrap();
         let (cols, rows) = (ts.0 as u32, ts.1 as u32);
-        
-        let term_size = Arc::new(Mutex::new(Size { width: cols, height: rows }));
-        let term_size_clone = Arc::clone(&term_size);
-
-        thread::spawn(move || {
-            loop {
-                if poll(Duration::from_millis(500)).unwrap() {
-                    match read().unwrap() {
-                        Event::Resize(width, height) => {
-                            let mut term_size = term_size_clone.lock().unwrap();
-                            term_size.width = width as u32;
-                            term_size.height = height as u32;
-                        },
-                        _ => {}
-                    }
-                }
-            }
-        });
 
         Self {
             frame_data,
-            term_size,
+            term_size: Size { width: cols, height: rows },
+            renderer: Box::new(Block::new(AnsiEncoder::new(ColorDepth::TrueColor))),
+            output: Vec::new(),
+            fit: Fit::Contain,
+            scaling: None,
+            cell_aspect: DEFAULT_CELL_ASPECT,
+            background: Color::Rgb { r: 0, g: 0, b: 0 },
+            filters: Vec::new(),
+            palette: None,
+            dither: None,
         }
     }
 
@@ -71,88 +74,202 @@ impl Canvas {
         self.frame_data.set_raw_data(buffer, Size {width: buffer_width, height: buffer_height});
     }
 
-    pub fn get_colored_output(character: char, color: Color) -> String {
-        let (r, g, b) = match color {
-            Color::Rgb{r, g, b} => (r, g, b),
-            _ => (0, 0, 0),
+    // Pull frames from the source and render each one until it ends
+    pub fn play(&mut self, source: &mut dyn FrameSource) -> Result<(), Box<dyn std::error::Error>> {
+        self.frame_data.set_color_format(source.color_format());
+
+        while let Some(buffer) = source.next_frame()? {
+            let size = source.size();
+
+            self.push_buffer(buffer, size.width, size.height);
+            self.render()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_renderer(&mut self, renderer: Box<dyn Renderer>) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_fit(&mut self, fit: Fit) {
+        self.fit = fit;
+    }
+
+    // Fixed scaling type, `None` averages areas on large reductions and scales bilinear otherwise
+    pub fn set_scaling(&mut self, scaling: Option<Scaling>) {
+        self.scaling = scaling;
+    }
+
+    // Screen height of a terminal cell over its width
+    pub fn set_cell_aspect(&mut self, cell_aspect: f64) {
+        self.cell_aspect = cell_aspect;
+    }
+
+    // Space frames are scaled in, linear light keeps thin bright detail from fading on downscale
+    pub fn set_blend_space(&mut self, blend_space: BlendSpace) {
+        self.frame_data.set_blend_space(blend_space);
+    }
+
+    // Runs on every frame once it is scaled to the terminal, before any palette remapping
+    pub fn add_filter(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+    }
+
+    // Remap every frame to a fixed palette or one built from its own colors
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
+    }
+
+    // Dithering used when remapping to the palette
+    pub fn set_dither(&mut self, dither: Option<Dither>) {
+        self.dither = dither;
+    }
+
+    // Color of the letterbox bars around a contained frame
+    pub fn set_background(&mut self, background: Color) {
+        self.background = background;
+    }
+
+    // Cells the renderer may draw into, without the rows it keeps free at the bottom
+    fn drawable_size(&self) -> Size {
+        Size {
+            width: self.term_size.width,
+            height: self.term_size.height.saturating_sub(self.renderer.reserved_rows()),
+        }
+    }
+
+    // Size to scale the frame to so it keeps its aspect ratio on screen, measured in renderer pixels
+    fn fitted_size(&self, image_size: Size, buffer_size: Size) -> Size {
+        let cell_pixels = self.renderer.cell_pixels();
+        // Screen size of one renderer pixel, in cell widths
+        let pixel_width = 1.0 / cell_pixels.width as f64;
+        let pixel_height = match self.renderer.square_pixels() {
+            true => pixel_width,
+            false => self.cell_aspect / cell_pixels.height as f64,
         };
-        format!("{}[38;2;{};{};{}m{}", 27 as char, r, g, b, character)
+        let scale_x = image_size.width as f64 * pixel_width / buffer_size.width.max(1) as f64;
+        let scale_y = image_size.height as f64 * pixel_height / buffer_size.height.max(1) as f64;
+        let scale = match self.fit {
+            Fit::Contain => scale_x.min(scale_y),
+            Fit::Cover => scale_x.max(scale_y),
+            Fit::Stretch => return image_size,
+        };
+
+        Size {
+            width: ((buffer_size.width as f64 * scale / pixel_width).round() as u32).max(1),
+            height: ((buffer_size.height as f64 * scale / pixel_height).round() as u32).max(1),
+        }
     }
 
-    pub unsafe fn render(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // Windows terminal stuff
-        SetConsoleOutputCP(CP_UTF8);
-        let h = winapi::um::winbase::STD_OUTPUT_HANDLE;
-        let console_handle = kernel32::GetStdHandle(h);
-        let mut stdout = File::from_raw_handle(console_handle as *mut _);
-        let term_size = self.term_size.lock().unwrap();
-        // End of Windows terminal stuff
+    pub fn render(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Asked for every frame instead of watching resize events, so nothing else reads the terminal's input
+        // and replies to renderer queries reach the renderer
+        if let Ok((cols, rows)) = terminal::size() {
+            self.term_size = Size { width: cols as u32, height: rows as u32 };
+        }
+
+        let term_size = self.drawable_size();
+        let cell_pixels = self.renderer.cell_pixels();
+        let image_size = Size {
+            width: term_size.width * cell_pixels.width,
+            height: term_size.height * cell_pixels.height,
+        };
         let buffer_size: Size = self.frame_data.get_buffer_size();
-        let pixel_data = self.frame_data.get_image_mut()
-            .scale(*term_size, Scaling::Bilinear, buffer_size)
-            //.seam_carve(false, false)
-            .get_pixel_data();
-        //let mut sobel_image = pixel_data.clone();
-        //sobel_image
-        //.grayscale(3)
-        //.gradient_magnitude(Kernel::Prewitt);
-        //.mask_ontop(pixel_data.grayscale(10 as u8).brightness(-50), Color::Rgb{r: 0, g: 0, b: 0}, 130);
-        //.get_pixel_data();
-
-        //let ascii_data = sobel_image.get_ascii("  `.-':_,^=;><+!rc*/z?sLTv)J7(|Fi{C}fI31tlu[neoZ5Yxjya]2ESwqkP6h9d4VpOGbUAKXHm8RD#$Bg0MNWQ%&@".to_string());
-        
-        //let pixel_data = sobel_image.get_pixel_data();
-        let mut colored_string = String::with_capacity(pixel_data.len());
-
-        //execute!(stdout, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
-        for (index, pixel) in pixel_data.iter().enumerate() {
-            let pixel = pixel.clone();
-            // From color to cross color
-            
-            //if self.last_buffer.len() > 0 && self.last_buffer.len() == pixel_data.len() {
-            //    let last_pixel = self.last_buffer[index].clone();
-            //    if last_pixel == pixel {
-            //        continue;
-            //    }
-            //}
-            // If almost black no render
-            let (r, g, b) = pixel.get_color_raw();
-
-            if r < 10 && g < 10 && b < 10 {
-                colored_string.push_str(&format!("{}", " "));
-
-                continue;
-            }
-
-            //colored_string.push_str(&format!("{}", "█".stylize().with(pixel.get_color())));
-
-            colored_string.push_str(&Self::get_colored_output(pixel.get_character(), pixel.get_color()));
-            // Write to stdout
-            //stdout.write_all(&format!("{}", "█".stylize().with(pixel)).as_bytes())?;
+        let fitted_size = self.fitted_size(image_size, buffer_size);
+        let scaling = match self.scaling {
+            Some(scaling) => scaling,
+            None if buffer_size.width >= fitted_size.width * AREA_SCALE_FACTOR
+                || buffer_size.height >= fitted_size.height * AREA_SCALE_FACTOR => Scaling::Area,
+            None => Scaling::Bilinear,
+        };
+        self.frame_data.get_image_mut()
+            .scale(fitted_size, scaling, buffer_size)
+            .crop(image_size)
+            .letterbox(image_size, self.background);
+
+        for filter in self.filters.iter_mut() {
+            filter.apply(&mut self.frame_data);
         }
-        let start = Instant::now();
-        execute!(stdout, 
-            cursor::MoveTo(0, 0),
-            style::Print(colored_string),
-            //cursor::MoveTo(0, 0),
-        )?;
-
-        //write!(stdout, "{}", "\x1B[H")?;
-        //write!(stdout, "{}", colored_string)?;
-        
-        let duration = start.elapsed();
-        println!("Render processing time: {:?}", duration);
-        stdout.flush()?;
 
-        mem::forget(stdout);
+        let image = self.frame_data.get_image_mut();
+
+        if let Some(palette) = &mut self.palette {
+            palette.remap(image, self.dither);
+        }
+
+        // Anything printed after the frame would scroll the screen and throw off the next frame's diff
+        self.output.clear();
+        queue!(self.output, cursor::MoveTo(0, 0))?;
+        self.renderer.render(image, term_size, &mut self.output);
+
+        // The whole frame is already in one buffer, a single write keeps the terminal from showing half of it
+        let mut stdout = io::stdout().lock();
+
+        stdout.write_all(&self.output)?;
+        stdout.flush()?;
 
         Ok(())
-        //print!("{esc}c", esc = 27 as char); // Clears terminal
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::render::sixel::Sixel;
+    use super::*;
+
+    fn canvas(renderer: Box<dyn Renderer>, fit: Fit, cell_aspect: f64) -> Canvas {
+        Canvas {
+            frame_data: FrameData::default(),
+            term_size: Size { width: 80, height: 24 },
+            renderer,
+            output: Vec::new(),
+            fit,
+            scaling: None,
+            cell_aspect,
+            background: Color::Rgb { r: 0, g: 0, b: 0 },
+            filters: Vec::new(),
+            palette: None,
+            dither: None,
+        }
+    }
+
+    fn fitted(canvas: &Canvas, image_size: (u32, u32), buffer_size: (u32, u32)) -> (u32, u32) {
+        let size = canvas.fitted_size(
+            Size { width: image_size.0, height: image_size.1 },
+            Size { width: buffer_size.0, height: buffer_size.1 },
+        );
+
+        (size.width, size.height)
+    }
 
-//Render processing time: 459.2432ms-925.8014ms Max windows terminal
-//Render processing time: 9.417ms Max windows terminal no color
-//Render processing time: 517.901ms
+    #[test]
+    fn cells_follow_the_cell_aspect() {
+        let block = || Box::new(Block::new(AnsiEncoder::new(ColorDepth::TrueColor)));
 
+        // A square frame on 80x24 cells twice as tall as wide is 48 cells wide
+        assert_eq!(fitted(&canvas(block(), Fit::Contain, 2.0), (80, 24), (100, 100)), (48, 24));
+        assert_eq!(fitted(&canvas(block(), Fit::Cover, 2.0), (80, 24), (100, 100)), (80, 40));
+        assert_eq!(fitted(&canvas(block(), Fit::Stretch, 2.0), (80, 24), (100, 100)), (80, 24));
+    }
+
+    #[test]
+    fn pixel_renderers_keep_pixels_square() {
+        let sixel = || Box::new(Sixel::new(Size { width: 10, height: 20 }));
+
+        // The cell aspect only describes character cells, an override must not stretch real pixels
+        assert_eq!(fitted(&canvas(sixel(), Fit::Contain, 2.5), (800, 480), (100, 100)), (480, 480));
+        assert_eq!(fitted(&canvas(sixel(), Fit::Cover, 2.5), (800, 480), (100, 50)), (960, 480));
+    }
+
+    #[test]
+    fn reserved_rows_are_left_out_of_the_fit() {
+        let sixel = canvas(Box::new(Sixel::new(Size { width: 10, height: 20 })), Fit::Contain, 2.0);
+        let block = canvas(Box::new(Block::new(AnsiEncoder::new(ColorDepth::TrueColor))), Fit::Contain, 2.0);
+        let drawable = sixel.drawable_size();
+
+        assert_eq!((drawable.width, drawable.height), (80, 23));
+        assert_eq!(block.drawable_size().height, 24);
+        assert_eq!(fitted(&sixel, (800, 460), (100, 100)), (460, 460));
+    }
+}
@@ -0,0 +1,356 @@
+use std::sync::OnceLock;
+
+/// sRGB transfer function, byte to linear light in 0..1
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = match value <= 0.0031308 {
+        true => value * 12.92,
+        false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+// Linear light steps in the encoding table, fine enough that every sRGB byte survives a round trip
+const ENCODE_STEPS: usize = 4096;
+
+fn decode_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+
+    TABLE.get_or_init(|| std::array::from_fn(|value| srgb_to_linear(value as u8)))
+}
+
+fn encode_table() -> &'static [u8] {
+    static TABLE: OnceLock<Vec<u8>> = OnceLock::new();
+
+    TABLE.get_or_init(|| (0..ENCODE_STEPS).map(|step| linear_to_srgb(step as f32 / (ENCODE_STEPS - 1) as f32)).collect())
+}
+
+/// Space channel values are mixed in when resampling or blending. Averaging raw sRGB bytes
+/// darkens fine detail, linear light keeps the brightness the eye sees.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum BlendSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl BlendSpace {
+    // Channel byte as a 0..255 value in this space
+    pub fn decode(&self, value: u8) -> f32 {
+        match self {
+            BlendSpace::Srgb => value as f32,
+            BlendSpace::Linear => decode_table()[value as usize] * 255.0,
+        }
+    }
+
+    pub fn encode(&self, value: f32) -> u8 {
+        let value = value.clamp(0.0, 255.0);
+
+        match self {
+            BlendSpace::Srgb => value.round() as u8,
+            BlendSpace::Linear => encode_table()[(value / 255.0 * (ENCODE_STEPS - 1) as f32).round() as usize],
+        }
+    }
+
+    // Rec. 709 weighted brightness, on raw bytes this is the video style luma
+    pub fn luminance(&self, color: (u8, u8, u8)) -> u8 {
+        self.encode(0.2126 * self.decode(color.0) + 0.7152 * self.decode(color.1) + 0.0722 * self.decode(color.2))
+    }
+}
+
+/// Relative luminance of a color as an sRGB byte, how bright it looks rather than the channel average
+pub fn luminance(color: (u8, u8, u8)) -> u8 {
+    BlendSpace::Linear.luminance(color)
+}
+
+/// A color model that converts to and from sRGB bytes
+pub trait ColorSpace {
+    fn from_rgb(color: (u8, u8, u8)) -> Self;
+    fn to_rgb(&self) -> (u8, u8, u8);
+}
+
+// Hue in degrees from channels in 0..1, shared by HSV and HSL
+fn hue(r: f32, g: f32, b: f32) -> f32 {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = match delta {
+        _ if delta == 0.0 => 0.0,
+        _ if max == r => ((g - b) / delta).rem_euclid(6.0),
+        _ if max == g => (b - r) / delta + 2.0,
+        _ => (r - g) / delta + 4.0,
+    };
+
+    hue * 60.0
+}
+
+// Channels in 0..1 for a hue, the chroma and the value of the smallest channel
+fn from_hue(hue: f32, chroma: f32, minimum: f32) -> (u8, u8, u8) {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let byte = |value: f32| ((value + minimum) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    (byte(r), byte(g), byte(b))
+}
+
+fn unit(color: (u8, u8, u8)) -> (f32, f32, f32) {
+    (color.0 as f32 / 255.0, color.1 as f32 / 255.0, color.2 as f32 / 255.0)
+}
+
+/// Hue in degrees, saturation and value in 0..1
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+impl ColorSpace for Hsv {
+    fn from_rgb(color: (u8, u8, u8)) -> Self {
+        let (r, g, b) = unit(color);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        Self {
+            h: hue(r, g, b),
+            s: if max == 0.0 { 0.0 } else { delta / max },
+            v: max,
+        }
+    }
+
+    fn to_rgb(&self) -> (u8, u8, u8) {
+        let chroma = self.v * self.s;
+
+        from_hue(self.h, chroma, self.v - chroma)
+    }
+}
+
+/// Hue in degrees, saturation and lightness in 0..1
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+impl ColorSpace for Hsl {
+    fn from_rgb(color: (u8, u8, u8)) -> Self {
+        let (r, g, b) = unit(color);
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let l = (max + min) / 2.0;
+        let delta = max - min;
+
+        Self {
+            h: hue(r, g, b),
+            s: if delta == 0.0 { 0.0 } else { delta / (1.0 - (2.0 * l - 1.0).abs()) },
+            l,
+        }
+    }
+
+    fn to_rgb(&self) -> (u8, u8, u8) {
+        let chroma = (1.0 - (2.0 * self.l - 1.0).abs()) * self.s;
+
+        from_hue(self.h, chroma, self.l - chroma / 2.0)
+    }
+}
+
+// D65 white point in XYZ
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+/// CIELAB (D65), L in 0..100
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl ColorSpace for Lab {
+    fn from_rgb(color: (u8, u8, u8)) -> Self {
+        let table = decode_table();
+        let (r, g, b) = (table[color.0 as usize], table[color.1 as usize], table[color.2 as usize]);
+        let xyz = [
+            0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+            0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+            0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+        ];
+        let f = |index: usize| {
+            let t = xyz[index] / WHITE[index];
+
+            match t > 216.0 / 24389.0 {
+                true => t.cbrt(),
+                false => t * 841.0 / 108.0 + 4.0 / 29.0,
+            }
+        };
+        let (fx, fy, fz) = (f(0), f(1), f(2));
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    fn to_rgb(&self) -> (u8, u8, u8) {
+        let fy = (self.l + 16.0) / 116.0;
+        let inverse = |t: f32| match t > 6.0 / 29.0 {
+            true => t * t * t,
+            false => 108.0 / 841.0 * (t - 4.0 / 29.0),
+        };
+        let x = WHITE[0] * inverse(fy + self.a / 500.0);
+        let y = WHITE[1] * inverse(fy);
+        let z = WHITE[2] * inverse(fy - self.b / 200.0);
+
+        (
+            linear_to_srgb(3.2404542 * x - 1.5371385 * y - 0.4985314 * z),
+            linear_to_srgb(-0.969266 * x + 1.8760108 * y + 0.041556 * z),
+            linear_to_srgb(0.0556434 * x - 0.2040259 * y + 1.0572252 * z),
+        )
+    }
+}
+
+/// Perceptually uniform color space, euclidean distance tracks how different two colors look
+/// https://bottosson.github.io/posts/oklab/
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Oklab {
+    pub fn from_rgb(color: (u8, u8, u8)) -> Self {
+        let table = decode_table();
+        let (r, g, b) = (table[color.0 as usize], table[color.1 as usize], table[color.2 as usize]);
+
+        let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+        let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+        let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+
+        Self {
+            l: 0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+            a: 1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+            b: 0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+        }
+    }
+
+    pub fn to_rgb(&self) -> (u8, u8, u8) {
+        let l = (self.l + 0.39633778 * self.a + 0.21580376 * self.b).powi(3);
+        let m = (self.l - 0.105561346 * self.a - 0.06385417 * self.b).powi(3);
+        let s = (self.l - 0.08948418 * self.a - 1.2914855 * self.b).powi(3);
+
+        (
+            linear_to_srgb(4.0767417 * l - 3.3077116 * m + 0.23096993 * s),
+            linear_to_srgb(-1.268438 * l + 2.6097574 * m - 0.34131938 * s),
+            linear_to_srgb(-0.0041960863 * l - 0.7034186 * m + 1.7076147 * s),
+        )
+    }
+
+    // Colorfulness, around 0.32 at most for sRGB colors
+    pub fn chroma(&self) -> f32 {
+        (self.a * self.a + self.b * self.b).sqrt()
+    }
+
+    // Turns the hue around the lightness axis, lightness and chroma stay put
+    pub fn rotate_hue(&mut self, degrees: f32) {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+
+        (self.a, self.b) = (self.a * cos - self.b * sin, self.a * sin + self.b * cos);
+    }
+
+    pub fn scale_chroma(&mut self, factor: f32) {
+        self.a *= factor;
+        self.b *= factor;
+    }
+
+    pub fn distance_squared(&self, other: &Oklab) -> f32 {
+        let dl = self.l - other.l;
+        let da = self.a - other.a;
+        let db = self.b - other.b;
+
+        dl * dl + da * da + db * db
+    }
+}
+
+impl ColorSpace for Oklab {
+    fn from_rgb(color: (u8, u8, u8)) -> Self {
+        Oklab::from_rgb(color)
+    }
+
+    fn to_rgb(&self) -> (u8, u8, u8) {
+        Oklab::to_rgb(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every 15th value per channel, corners of the RGB cube included
+    fn grid() -> impl Iterator<Item = (u8, u8, u8)> {
+        (0..=255u32).step_by(15)
+            .flat_map(|r| (0..=255u32).step_by(15).map(move |g| (r, g)))
+            .flat_map(|(r, g)| (0..=255u32).step_by(15).map(move |b| (r as u8, g as u8, b as u8)))
+    }
+
+    fn assert_round_trip<T: ColorSpace>(tolerance: u8) {
+        for color in grid() {
+            let (r, g, b) = T::from_rgb(color).to_rgb();
+
+            assert!(
+                r.abs_diff(color.0) <= tolerance && g.abs_diff(color.1) <= tolerance && b.abs_diff(color.2) <= tolerance,
+                "{:?} came back as {:?}", color, (r, g, b),
+            );
+        }
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        assert_round_trip::<Hsv>(0);
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        assert_round_trip::<Hsl>(0);
+    }
+
+    #[test]
+    fn lab_round_trip() {
+        assert_round_trip::<Lab>(1);
+    }
+
+    #[test]
+    fn oklab_round_trip() {
+        assert_round_trip::<Oklab>(1);
+    }
+
+    #[test]
+    fn known_values() {
+        let red = Hsv::from_rgb((255, 0, 0));
+        let lab = Lab::from_rgb((255, 255, 255));
+        let oklab = Oklab::from_rgb((255, 255, 255));
+
+        assert_eq!((red.h, red.s, red.v), (0.0, 1.0, 1.0));
+        assert_eq!(Hsl::from_rgb((0, 0, 255)).h, 240.0);
+        assert!((lab.l - 100.0).abs() < 0.01 && lab.a.abs() < 0.01 && lab.b.abs() < 0.01);
+        assert!((oklab.l - 1.0).abs() < 0.001 && oklab.chroma() < 0.001);
+        assert_eq!(luminance((255, 255, 255)), 255);
+    }
+}
@@ -1,6 +1,7 @@
 //use termcolor::Color;
 use crossterm::style::Color;
-use crate::processing::{ImageProcess, Scaling, Energy, Kernel};
+use crate::color::{self, BlendSpace, ColorSpace, Oklab};
+use crate::processing::{dither::{self, Diffusion, Dither}, palette::PaletteMatcher, resample, ImageProcess, Scaling, Energy, Kernel};
 use crate::{
     pixel,
     vector2,
@@ -13,11 +14,32 @@ pub struct Size {
     pub height: u32,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorFormat {
+    Rgba16F,
+    Rgba8,
+    Bgra8,
+    Rgb8,
+}
+
+impl ColorFormat {
+    // Bytes per pixel in a raw buffer
+    pub fn multiplier(&self) -> u8 {
+        match self {
+            ColorFormat::Rgba16F => 8,
+            ColorFormat::Rgba8 => 4,
+            ColorFormat::Bgra8 => 4,
+            ColorFormat::Rgb8 => 3,
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct Image {
     pub pixel_data: Vec<PxData>,
     pub size: Size,
     multiplier: u8,
+    blend_space: BlendSpace,
 }
 
 #[derive(Default, Clone)]
@@ -26,19 +48,21 @@ pub struct FrameData {
     raw_data: Vec<u8>,
     image: Image,
     multiplier: u8,
+    // Blue comes first in the raw buffer
+    bgr: bool,
+    blend_space: BlendSpace,
 }
 
 
 impl FrameData {
     pub fn new(width: u32, height: u32, multiplier: u8) -> Self {
-        let buffer_width = 0;
-        let buffer_height = 0;
-
         Self {
-            buffer_size: Size{width: buffer_width, height: buffer_height},
+            buffer_size: Size{width, height},
             raw_data: Vec::new(),
             image: Image::default(),
             multiplier,
+            bgr: false,
+            blend_space: BlendSpace::Srgb,
         }
     }
     
@@ -50,6 +74,17 @@ impl FrameData {
         self.multiplier = multiplier;
     }
 
+    pub fn set_color_format(&mut self, color_format: ColorFormat) {
+        self.multiplier = color_format.multiplier();
+        self.bgr = color_format == ColorFormat::Bgra8;
+    }
+
+    // Carried over to every image built from a new buffer
+    pub fn set_blend_space(&mut self, blend_space: BlendSpace) {
+        self.blend_space = blend_space;
+        self.image.set_blend_space(blend_space);
+    }
+
     pub fn set_raw_data(&mut self, data: Vec<u8>, buffer_size: Size) {
         let step = self.multiplier as usize;
         let mut pixel_data = Vec::new();
@@ -60,13 +95,18 @@ impl FrameData {
         for i in (0..self.raw_data.len()).step_by(step) {
             let quotient = (i / step) as u32;
             let x = quotient % self.buffer_size.width;
-            let y = quotient / self.buffer_size.height;
-            let pixel = pixel!(self.raw_data[i], self.raw_data[i + 1], self.raw_data[i + 2], x, y);
+            let y = quotient / self.buffer_size.width;
+            let (r, b) = match self.bgr {
+                true => (self.raw_data[i + 2], self.raw_data[i]),
+                false => (self.raw_data[i], self.raw_data[i + 2]),
+            };
+            let pixel = pixel!(r, self.raw_data[i + 1], b, x, y);
 
             pixel_data.push(pixel);
         }
 
         self.image = Image::new(pixel_data, self.buffer_size, self.multiplier);
+        self.image.set_blend_space(self.blend_space);
     }
 
     pub fn raw_buffer(&self) -> &Vec<u8> {
@@ -96,6 +136,7 @@ impl Image {
             pixel_data,
             size,
             multiplier,
+            blend_space: BlendSpace::Srgb,
         }
     }
 
@@ -112,6 +153,7 @@ impl Image {
             pixel_data,
             size,
             multiplier: 4,
+            blend_space: BlendSpace::Srgb,
         }
     }
 
@@ -119,10 +161,45 @@ impl Image {
         self.size
     }
 
+    pub fn get_multiplier(&self) -> u8 {
+        self.multiplier
+    }
+
     pub fn set_image_size(&mut self, size: Size) {
         self.size = size;
     }
 
+    pub fn get_blend_space(&self) -> BlendSpace {
+        self.blend_space
+    }
+
+    pub fn set_blend_space(&mut self, blend_space: BlendSpace) {
+        self.blend_space = blend_space;
+    }
+
+    // Every pixel's color converted to another model, in pixel order
+    pub fn to_space<T: ColorSpace>(&self) -> Vec<T> {
+        self.pixel_data.iter().map(|pixel| pixel.to_space()).collect()
+    }
+
+    pub fn set_from_space<T: ColorSpace>(&mut self, colors: &[T]) {
+        for (pixel, color) in self.pixel_data.iter_mut().zip(colors) {
+            pixel.set_from_space(color);
+        }
+    }
+
+    // Runs `change` on every pixel in OKLab, where hue and chroma edits keep the perceived lightness
+    fn map_oklab(&mut self, change: impl Fn(&mut Oklab)) -> &mut Self {
+        for pixel in self.pixel_data.iter_mut() {
+            let mut oklab: Oklab = pixel.to_space();
+
+            change(&mut oklab);
+            pixel.set_from_space(&oklab);
+        }
+
+        self
+    }
+
     fn get_pixel_index(&self, position: Vector2) -> usize {
         (position.get_y() * self.get_image_size().width + position.get_x()) as usize
     }
@@ -151,7 +228,7 @@ impl ImageProcess for Image {
         let size: Size = self.size;
         let pixel_data = self.get_pixel_data();
         
-        match pixel_data.len() > 0 && size.width > 0 && size.height > 0 {
+        match !pixel_data.is_empty() && size.width > 0 && size.height > 0 {
             true => {
                 let mut flipped_data = vec![PxData::default(); pixel_data.len()];
                 let width = size.width;
@@ -177,31 +254,127 @@ impl ImageProcess for Image {
         self
     }
 
-    fn quantize(&mut self, shades: u8) -> &mut Self {
-        for pixel in self.get_pixel_data_mut().iter_mut() {
+    fn quantize(&mut self, shades: u8, diffusion: Option<Diffusion>) -> &mut Self {
+        let diffusion = match diffusion {
+            Some(diffusion) => diffusion,
+            None => {
+                for pixel in self.get_pixel_data_mut().iter_mut() {
+                    pixel.quantize(shades);
+                }
+
+                return self;
+            }
+        };
+        let colors: Vec<Option<[f32; 3]>> = self.get_pixel_data().iter()
+            .map(|pixel| {
+                let (r, g, b) = pixel.get_color_raw();
+
+                Some([r as f32, g as f32, b as f32])
+            })
+            .collect();
+        let width = self.size.width as usize;
+        let pixel_data = &mut self.pixel_data;
+
+        dither::diffuse(&colors, width, diffusion, |index, wanted| {
+            let pixel = &mut pixel_data[index];
+            let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+
+            pixel.set_color_raw(channel(wanted[0]), channel(wanted[1]), channel(wanted[2]));
             pixel.quantize(shades);
-        }
+
+            let (r, g, b) = pixel.get_color_raw();
+
+            [r as f32, g as f32, b as f32]
+        });
 
         self
     }
 
-    fn grayscale(&mut self, shades: u8) -> &mut Self {
+    fn grayscale(&mut self, shades: u8, diffusion: Option<Diffusion>) -> &mut Self {
         match shades >= 2 {
             true => {
-                let factor = 255 / (shades - 1) as u8;
+                let factor = 255 / (shades - 1);
+                let space = self.blend_space;
+                let averages: Vec<f64> = self.get_pixel_data().iter()
+                    .map(|pixel| space.luminance(pixel.get_color_raw()) as f64)
+                    .collect();
+
+                match diffusion {
+                    Some(diffusion) => {
+                        let colors: Vec<Option<[f32; 3]>> = averages.iter().map(|average| Some([*average as f32; 3])).collect();
+                        let width = self.size.width as usize;
+                        let pixel_data = &mut self.pixel_data;
+
+                        // Nearest shade rather than rounding up, the diffused error takes care of the rest
+                        dither::diffuse(&colors, width, diffusion, |index, wanted| {
+                            let gray = ((wanted[0] / factor as f32).round() * factor as f32).clamp(0.0, 255.0) as u8;
+
+                            pixel_data[index].set_color_raw(gray, gray, gray);
+                            [gray as f32; 3]
+                        });
+                    },
+                    None => {
+                        for (pixel, average) in self.get_pixel_data_mut().iter_mut().zip(averages) {
+                            let gray = (average / factor as f64).ceil() * factor as f64;
+
+                            pixel.set_color_raw(gray as u8, gray as u8, gray as u8);
+                        }
+                    },
+                }
+            },
+            false => {
+                panic!("Shades must be between 2 and 255")
+            }
+        }
+        self
+    }
+
+    // Every pixel becomes its nearest palette entry, like `quantize` with an arbitrary set of colors
+    fn remap(&mut self, matcher: &mut PaletteMatcher, dither: Option<Dither>) -> &mut Self {
+        let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+
+        match dither {
+            Some(Dither::Diffusion(diffusion)) => {
+                let colors: Vec<Option<[f32; 3]>> = self.get_pixel_data().iter()
+                    .map(|pixel| {
+                        let (r, g, b) = pixel.get_color_raw();
+
+                        Some([r as f32, g as f32, b as f32])
+                    })
+                    .collect();
+                let width = self.size.width as usize;
+                let pixel_data = &mut self.pixel_data;
+
+                dither::diffuse(&colors, width, diffusion, |index, wanted| {
+                    let palette_index = matcher.nearest((channel(wanted[0]), channel(wanted[1]), channel(wanted[2])));
+                    let (r, g, b) = matcher.color(palette_index);
+
+                    pixel_data[index].set_color_raw(r, g, b);
+                    [r as f32, g as f32, b as f32]
+                });
+            },
+            Some(Dither::Ordered(matrix)) => {
+                let spread = matcher.spread();
 
                 for pixel in self.get_pixel_data_mut().iter_mut() {
+                    let offset = (matrix.threshold(pixel.get_x(), pixel.get_y()) - 0.5) * spread;
                     let (r, g, b) = pixel.get_color_raw();
-                    let average = (r as f64 + g as f64 + b as f64) / 3.0;
-                    let gray = (average / factor as f64).ceil() * factor as f64;
+                    let index = matcher.nearest((channel(r as f32 + offset), channel(g as f32 + offset), channel(b as f32 + offset)));
+                    let (r, g, b) = matcher.color(index);
 
-                    pixel.set_color_raw(gray as u8, gray as u8, gray as u8);
+                    pixel.set_color_raw(r, g, b);
+                }
+            },
+            None => {
+                for pixel in self.get_pixel_data_mut().iter_mut() {
+                    let index = matcher.nearest(pixel.get_color_raw());
+                    let (r, g, b) = matcher.color(index);
+
+                    pixel.set_color_raw(r, g, b);
                 }
             },
-            false => {
-                panic!("Shades must be between 2 and 255")
-            }
         }
+
         self
     }
 
@@ -210,6 +383,7 @@ impl ImageProcess for Image {
 
         let buffer_width = original_size.width;
         let buffer_height = original_size.height;
+        let space = self.blend_space;
         let mut resized_pixel_data = Vec::new();
 
         match scaling {
@@ -226,65 +400,20 @@ impl ImageProcess for Image {
                 self.set_image_size(size);
                 self.pixel_data = resized_pixel_data;
             },
-            Scaling::Bilinear => { //https://x-engineer.org/bilinear-interpolation/
-                macro_rules! bilinear_interpolate {
-                    ($x:expr, $y:expr, $x1:expr, $y1:expr, $x2:expr, $y2:expr, $q11:expr, $q21:expr, $q12:expr, $q22:expr) => {{
-                        let x1 = $x1 as f64;
-                        let x2 = $x2 as f64;
-                        let y1 = $y1 as f64;
-                        let y2 = $y2 as f64;
-
-                        let r1 = ($q11 as f64 * (x2 - $x) + $q21 as f64 * ($x - x1)) / (x2 - x1);
-                        let r2 = ($q12 as f64 * (x2 - $x) + $q22 as f64 * ($x - x1)) / (x2 - x1);
-                        let p = (r1 * (y2 - $y) + r2 * ($y - y1)) / (y2 - y1);
-
-                        p.round() as u8
-                    }};
-                }
-
-                for y in 0..height {
-                    for x in 0..width {
-                        let src_x = (x as f64 * (buffer_width - 1) as f64 / (width - 1) as f64) as f64;
-                        let src_y = (y as f64 * (buffer_height - 1) as f64 / (height - 1) as f64) as f64;
-                        let x1 = src_x.floor() as u32;
-                        let x2 = (x1 + 1).min(buffer_width - 1);
-                        let y1 = src_y.floor() as u32;
-                        let y2 = (y1 + 1).min(buffer_height - 1);
-                        
-                        let index11 = (y1 * buffer_width + x1) as usize;
-                        let index21 = (y1 * buffer_width + x2) as usize;
-                        let index12 = (y2 * buffer_width + x1) as usize;
-                        let index22 = (y2 * buffer_width + x2) as usize;
-                        let q11 = self.get_pixel_data()[index11];
-                        let q21 = self.get_pixel_data()[index21];
-                        let q12 = self.get_pixel_data()[index12];
-                        let q22 = self.get_pixel_data()[index22];
-
-                        let r = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_r(), q21.get_r(), q12.get_r(), q22.get_r());
-                        let g = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_g(), q21.get_g(), q12.get_g(), q22.get_g());
-                        let b = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_b(), q21.get_b(), q12.get_b(), q22.get_b());
-
-                        let new_pixel = pixel!(r, g, b, x, y);
-                        resized_pixel_data.push(new_pixel);
-                    }
-                }
+            // The resampler widens the triangle when shrinking, so every source pixel still counts
+            Scaling::Bilinear | Scaling::Bicubic | Scaling::Lanczos(_) | Scaling::Area => {
+                let resized_pixel_data = resample::resample(self.get_pixel_data(), original_size, size, &scaling, space);
 
                 self.set_image_size(size);
                 self.pixel_data = resized_pixel_data;
             },
-            _ => {
-                panic!("Invalid scaling type");
-            }
         }
 
         self
     }
 
     // Direction: true = horizontal, false = vertical
-    fn seam_carve(&mut self, direction: bool, remove: bool) -> &mut Self {
+    fn seam_carve(&mut self, _direction: bool, _remove: bool) -> &mut Self {
         // Get the energy map
         let mut energy_map = Energy::new(
             self.get_pixel_data().clone(), 
@@ -292,7 +421,6 @@ impl ImageProcess for Image {
             self.get_image_size().height, 
             Kernel::Sobel
         );
-        let size = self.get_image_size();
 
         energy_map.compute_gradient_magnitude();
 
@@ -300,7 +428,7 @@ impl ImageProcess for Image {
 
         // Dynamic programming
         // Find seams
-        let mut seams = energy_map.find_seams(100);
+        let seams = energy_map.find_seams(100);
 
         energy_map.remove_seams(seams, true);
 
@@ -323,20 +451,18 @@ impl ImageProcess for Image {
     }
 
     // Combine two images based on a threshold and a color
-    fn mask_ontop(&mut self, other: &Image, color_mask: Color, threshold: u8) -> &mut Self {
+    fn mask_ontop(&mut self, other: &Image, _color_mask: Color, threshold: u8) -> &mut Self {
         let mut new_pixel_data = Vec::new();
         let (width, height) = (self.get_image_size().width, self.get_image_size().height);
+        let space = self.blend_space;
 
         for y in 0..height {
             for x in 0..width {
-                let index = (y * width + x) as usize;
                 let pixel = self.get_pixel(vector2!(x, y));
                 let other_pixel = other.get_pixel(vector2!(x, y));
                 let (r, g, b) = pixel.get_color_raw();
                 let (r2, g2, b2) = other_pixel.get_color_raw();
-                let average = ((r as i32 + g as i32 + b as i32) / 3) as u8;
-                let average2 = ((r2 as i32 + g2 as i32 + b2 as i32) / 3) as u8;
-                let diff = (average as i32 - average2 as i32).abs() as u8;
+                let diff = space.luminance((r, g, b)).abs_diff(space.luminance((r2, g2, b2)));
 
                 if diff > threshold {
                     new_pixel_data.push(pixel);
@@ -357,8 +483,7 @@ impl ImageProcess for Image {
         let pixel_data = self.get_pixel_data();
 
         for pixel in pixel_data.iter() {
-            let (r, g, b) = pixel.get_color_raw();
-            let average = ((r as f64 + g as f64 + b as f64) / 3.0) as f64;
+            let average = color::luminance(pixel.get_color_raw()) as f64;
             let shade_index = (average / 255.0 * shades_len).floor() as usize;
             let shade = shades[shade_index.min(shades_len as usize - 1)];
 
@@ -371,13 +496,123 @@ impl ImageProcess for Image {
     fn brightness(&mut self, value: i32) -> &mut Self {
         for pixel in self.get_pixel_data_mut().iter_mut() {
             let (r, g, b) = pixel.get_color_raw();
-            let r = (r as i32 + value).max(0).min(255) as u8;
-            let g = (g as i32 + value).max(0).min(255) as u8;
-            let b = (b as i32 + value).max(0).min(255) as u8;
+            let r = (r as i32 + value).clamp(0, 255) as u8;
+            let g = (g as i32 + value).clamp(0, 255) as u8;
+            let b = (b as i32 + value).clamp(0, 255) as u8;
 
             pixel.set_color_raw(r, g, b);
         }
 
         self
     }
-}
\ No newline at end of file
+
+    fn hue_rotate(&mut self, degrees: f32) -> &mut Self {
+        self.map_oklab(|oklab| oklab.rotate_hue(degrees))
+    }
+
+    // 0 is gray, 1 leaves the image as is
+    fn saturation(&mut self, factor: f32) -> &mut Self {
+        self.map_oklab(|oklab| oklab.scale_chroma(factor.max(0.0)))
+    }
+
+    // Saturation boost that fades out for colors that are already vivid, so skin tones don't go garish
+    fn vibrance(&mut self, amount: f32) -> &mut Self {
+        self.map_oklab(|oklab| {
+            let muted = 1.0 - (oklab.chroma() / 0.32).min(1.0);
+
+            oklab.scale_chroma((1.0 + amount * muted).max(0.0));
+        })
+    }
+
+    // Shifts OKLab lightness by `amount` in -1..1
+    fn lightness(&mut self, amount: f32) -> &mut Self {
+        self.map_oklab(|oklab| oklab.l = (oklab.l + amount).clamp(0.0, 1.0))
+    }
+
+    // Centers the image on a `size` canvas filled with the background color
+    fn letterbox(&mut self, size: Size, background: Color) -> &mut Self {
+        let image_size = self.get_image_size();
+        let left = size.width.saturating_sub(image_size.width) / 2;
+        let top = size.height.saturating_sub(image_size.height) / 2;
+        let mut padded = Vec::with_capacity((size.width * size.height) as usize);
+
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let inside = x >= left && x < left + image_size.width && y >= top && y < top + image_size.height;
+                let mut pixel = match inside {
+                    true => self.get_pixel(vector2!(x - left, y - top)),
+                    false => PxData::new(background, vector2!(x, y)),
+                };
+
+                pixel.set_position(vector2!(x, y));
+                padded.push(pixel);
+            }
+        }
+
+        self.set_image_size(size);
+        self.pixel_data = padded;
+        self
+    }
+
+    // Keeps the center `size` part of the image
+    fn crop(&mut self, size: Size) -> &mut Self {
+        let image_size = self.get_image_size();
+        let size = Size {
+            width: size.width.min(image_size.width),
+            height: size.height.min(image_size.height),
+        };
+        let left = (image_size.width - size.width) / 2;
+        let top = (image_size.height - size.height) / 2;
+        let mut cropped = Vec::with_capacity((size.width * size.height) as usize);
+
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let mut pixel = self.get_pixel(vector2!(x + left, y + top));
+
+                pixel.set_position(vector2!(x, y));
+                cropped.push(pixel);
+            }
+        }
+
+        self.set_image_size(size);
+        self.pixel_data = cropped;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source::pattern::{Pattern, PatternSource};
+    use super::*;
+
+    // A 2x2 black and white checker averaged down to a single pixel
+    fn checker_average(blend_space: BlendSpace, scaling: Scaling) -> (u8, u8, u8) {
+        let size = Size { width: 2, height: 2 };
+        let mut frame_data = PatternSource::new(Pattern::Checkerboard { cell: 1 }, size, 0.0).frame_data(0);
+
+        frame_data.set_blend_space(blend_space);
+        frame_data.get_image_mut()
+            .scale(Size { width: 1, height: 1 }, scaling, size)
+            .get_pixel(vector2!(0, 0))
+            .get_color_raw()
+    }
+
+    #[test]
+    fn linear_checker_averages_to_half_the_light() {
+        // Half of the light of white is 188 in sRGB
+        for scaling in [Scaling::Area, Scaling::Bilinear, Scaling::Bicubic, Scaling::Lanczos(3)] {
+            let (r, g, b) = checker_average(BlendSpace::Linear, scaling);
+
+            for channel in [r, g, b] {
+                assert!(channel.abs_diff(188) <= 1, "{}", channel);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_checker_averages_the_bytes() {
+        for scaling in [Scaling::Area, Scaling::Bilinear, Scaling::Bicubic, Scaling::Lanczos(3)] {
+            assert_eq!(checker_average(BlendSpace::Srgb, scaling), (128, 128, 128));
+        }
+    }
+}
@@ -1,79 +1,266 @@
-extern crate kernel32;
+#[cfg(windows)]
 extern crate winapi;
 
-use std::time::Instant;
-use windows_capture::{
-    capture::GraphicsCaptureApiHandler,
-    frame::Frame,
-    graphics_capture_api::InternalCaptureControl,
-    window::Window,
-    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
-};
+use std::{env, io};
 
+pub mod color;
 pub mod pixel;
 pub mod frame;
 pub mod processing;
+pub mod render;
+pub mod source;
 pub mod term;
-mod canvas;
-use canvas::Canvas;
+pub mod canvas;
+use canvas::{Canvas, Fit};
+use color::BlendSpace;
+use crossterm::style::Color;
+use processing::dither::{Diffusion, Dither};
+use processing::palette::{self, Metric, Palette};
+use processing::{filter, Scaling};
+use render::ansi::AnsiEncoder;
+use source::{FrameSource, SourceError};
+use term::ColorDepth;
 
-struct Capture {
-    fps: usize,
-    last_output: Instant,
-    canvas: Canvas,
+const USAGE: &str = "Usage: terminal_render <source> [--mode <name[=threshold]>] [--colors <truecolor|256|16|mono>] [--dither]
+    [--dither-method <floyd-steinberg|atkinson|jjn|sierra|bayer2-16|blue-noise>] [--tolerance <0-255>]
+    [--fit <contain|cover|stretch|fit|fill>] [--background <rrggbb>] [--cell-aspect <height/width>] [--linear-light]
+    [--scaling <auto|nearest|bilinear|bicubic|lanczos2|lanczos3|area>] [--filter <name[=value]>]...
+    [--palette <median-cut|octree|kmeans|gameboy|cga|solarized|path>] [--palette-colors <1-256>]
+    [--metric <rgb|redmean|oklab>]
+    terminal_render window <title>
+    terminal_render x11 <title> [fps]
+    terminal_render image <path> [--watch]
+    terminal_render pattern <bars|gradient|checkerboard|circle|zoneplate|grid> <width> <height> [fps]
+    terminal_render y4m
+    terminal_render rgba <width> <height> <fps>";
+
+// Removes `--name value` from the arguments and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+
+    args.remove(index);
+    match index < args.len() {
+        true => Some(args.remove(index)),
+        false => None,
+    }
+}
+
+// Removes `name` from the arguments, true if it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let count = args.len();
+
+    args.retain(|arg| arg != name);
+    args.len() != count
 }
 
-impl GraphicsCaptureApiHandler for Capture {
-    type Flags = Option<String>;
-    type Error = Box<dyn std::error::Error + Send + Sync>;
+fn open_source(args: &[String]) -> Result<Box<dyn FrameSource>, SourceError> {
+    match args.first().map(String::as_str) {
+        #[cfg(windows)]
+        Some("window") => {
+            let window_title = args.get(1).map(String::as_str).unwrap_or("");
+
+            Ok(Box::new(source::windows::WindowCapture::from_contains_name(window_title)?))
+        },
+        #[cfg(target_os = "linux")]
+        Some("x11") => {
+            let window_title = args.get(1).map(String::as_str).unwrap_or("");
+            let fps = args.get(2).map(|fps| fps.parse()).transpose()?.unwrap_or(30.0);
+
+            Ok(Box::new(source::x11::X11Capture::from_contains_name(window_title, fps)?))
+        },
+        Some("image") => {
+            let path = args.get(1).ok_or("Missing image path")?;
+            let watch_resize = args.iter().any(|arg| arg == "--watch");
 
-    fn new(_message: Self::Flags) -> Result<Self, Self::Error> {
-        let canvas = Canvas::new(ColorFormat::Rgba8);
+            Ok(Box::new(source::image_file::ImageFile::open(path, watch_resize)?))
+        },
+        Some("pattern") => {
+            let name = args.get(1).ok_or("Missing pattern name")?;
+            let pattern = source::pattern::Pattern::from_name(name).ok_or("Unknown pattern")?;
+            let width = args.get(2).ok_or("Missing width")?.parse()?;
+            let height = args.get(3).ok_or("Missing height")?.parse()?;
+            let fps = args.get(4).map(|fps| fps.parse()).transpose()?.unwrap_or(30.0);
+            let size = frame::Size { width, height };
+
+            Ok(Box::new(source::pattern::PatternSource::new(pattern, size, fps)))
+        },
+        Some("y4m") => Ok(Box::new(source::video::Y4m::new(io::stdin())?)),
+        Some("rgba") => {
+            let width = args.get(1).ok_or("Missing width")?.parse()?;
+            let height = args.get(2).ok_or("Missing height")?.parse()?;
+            let fps = args.get(3).ok_or("Missing fps")?.parse()?;
+
+            Ok(Box::new(source::video::RawVideo::new(io::stdin(), width, height, fps)))
+        },
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mode = take_option(&mut args, "--mode").unwrap_or("auto".to_string());
+    let colors = take_option(&mut args, "--colors");
+    let dither = take_flag(&mut args, "--dither");
+    let dither_method = match take_option(&mut args, "--dither-method").as_deref() {
+        None => Dither::Diffusion(Diffusion::FloydSteinberg),
+        Some(name) => match Dither::from_name(name) {
+            Some(dither_method) => dither_method,
+            None => {
+                eprintln!("Unknown dither method {}", name);
+                return;
+            }
+        },
+    };
+    let tolerance = match take_option(&mut args, "--tolerance").map(|tolerance| tolerance.parse()) {
+        None => 0,
+        Some(Ok(tolerance)) => tolerance,
+        Some(Err(_)) => {
+            eprintln!("Tolerance must be between 0 and 255");
+            return;
+        }
+    };
+    let fit = match take_option(&mut args, "--fit").as_deref() {
+        None | Some("contain" | "fit") => Fit::Contain,
+        Some("cover" | "fill") => Fit::Cover,
+        Some("stretch") => Fit::Stretch,
+        Some(fit) => {
+            eprintln!("Unknown fit {}", fit);
+            return;
+        }
+    };
+    let background = match take_option(&mut args, "--background").map(|color| palette::parse_hex(&color)) {
+        None => (0, 0, 0),
+        Some(Some(background)) => background,
+        Some(None) => {
+            eprintln!("Background must be a hex color like 1e1e2e");
+            return;
+        }
+    };
+    let scaling = match take_option(&mut args, "--scaling").as_deref() {
+        None | Some("auto") => None,
+        Some(name) => match Scaling::from_name(name) {
+            Some(scaling) => Some(scaling),
+            None => {
+                eprintln!("Unknown scaling {}", name);
+                return;
+            }
+        },
+    };
+    let mut filters = Vec::new();
 
-        Ok(Self {
-            fps: 0,
-            last_output: Instant::now(),
-            canvas,
-        })
+    while let Some(spec) = take_option(&mut args, "--filter") {
+        match filter::from_spec(&spec) {
+            Some(filter) => filters.push(filter),
+            None => {
+                eprintln!("Unknown filter {}", spec);
+                return;
+            }
+        }
     }
 
-    fn on_frame_arrived(
-        &mut self,
-        frame: &mut Frame,
-        _capture_control: InternalCaptureControl,
-    ) -> Result<(), Self::Error> {
-        let mut data = frame.buffer()?;
-        self.canvas.push_buffer(data.as_raw_buffer().to_vec(), frame.width(), frame.height());
-        unsafe{self.canvas.render().unwrap()};
-
-        self.fps += 1;
-
-        if self.last_output.elapsed().as_secs() >= 1 {
-            //println!("FPS: {}", self.fps);
-            self.fps = 0;
-            self.last_output = Instant::now();
+    let cell_aspect = match take_option(&mut args, "--cell-aspect").map(|aspect| aspect.parse::<f64>()) {
+        None => None,
+        Some(Ok(aspect)) if aspect.is_finite() && aspect > 0.0 => Some(aspect),
+        Some(_) => {
+            eprintln!("Cell aspect must be a positive number like 2.0");
+            return;
+        }
+    };
+    let palette_colors = match take_option(&mut args, "--palette-colors").map(|colors| colors.parse::<usize>()) {
+        None => 16,
+        Some(Ok(colors)) if (1..=256).contains(&colors) => colors,
+        Some(_) => {
+            eprintln!("Palette colors must be between 1 and 256");
+            return;
+        }
+    };
+    let metric = match take_option(&mut args, "--metric").as_deref() {
+        None => Metric::Oklab,
+        Some(name) => match Metric::from_name(name) {
+            Some(metric) => metric,
+            None => {
+                eprintln!("Unknown metric {}", name);
+                return;
+            }
+        },
+    };
+    let palette = match take_option(&mut args, "--palette").map(|name| Palette::from_name(&name, palette_colors, metric)) {
+        None => None,
+        Some(Ok(palette)) => Some(palette),
+        Some(Err(error)) => {
+            eprintln!("Could not load palette: {}", error);
+            return;
+        }
+    };
+    let blend_space = match take_flag(&mut args, "--linear-light") {
+        true => BlendSpace::Linear,
+        false => BlendSpace::Srgb,
+    };
+    let mut capabilities = term::probe_terminal();
+
+    capabilities.color_depth = match colors.as_deref() {
+        None => capabilities.color_depth,
+        Some("truecolor") => ColorDepth::TrueColor,
+        Some("256") => ColorDepth::Ansi256,
+        Some("16") => ColorDepth::Ansi16,
+        Some("mono") => ColorDepth::Monochrome,
+        Some(colors) => {
+            eprintln!("Unknown color depth {}", colors);
+            return;
+        }
+    };
+
+    let dither = match dither {
+        true => Some(dither_method),
+        false => None,
+    };
+    let mut encoder = AnsiEncoder::new(capabilities.color_depth);
+
+    // With a palette the frame is dithered while remapping, dithering the terminal colors again only adds noise
+    encoder.set_dither(match palette {
+        Some(_) => None,
+        None => dither,
+    });
+    encoder.set_tolerance(tolerance);
+
+    let renderer = match render::from_name(&mode, &capabilities, encoder) {
+        Some(renderer) => renderer,
+        None => {
+            eprintln!("Unknown render mode {}", mode);
+            return;
         }
+    };
+    let mut source = match open_source(&args) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}", error);
+            return;
+        }
+    };
+    let mut canvas = Canvas::new(source.color_format());
+
+    canvas.set_renderer(renderer);
+    canvas.set_fit(fit);
+    canvas.set_scaling(scaling);
+    canvas.set_background(Color::Rgb { r: background.0, g: background.1, b: background.2 });
+    canvas.set_blend_space(blend_space);
+    canvas.set_palette(palette);
+    canvas.set_dither(dither);
 
-        Ok(())
+    for filter in filters {
+        canvas.add_filter(filter);
     }
 
-    fn on_closed(&mut self) -> Result<(), Self::Error> {
-        println!("Capture Session Closed");
+    // Reported cell size beats the default, an explicit ratio beats both
+    if let Some(cell_pixels) = capabilities.cell_pixels {
+        canvas.set_cell_aspect(cell_pixels.height as f64 / cell_pixels.width as f64);
+    }
 
-        Ok(())
+    if let Some(cell_aspect) = cell_aspect {
+        canvas.set_cell_aspect(cell_aspect);
     }
-}
 
-fn main() {
-    let window_title = "";
-    let window = Window::from_contains_name(window_title).expect("Failed to find window");
-    let settings = Settings::new(
-        window,
-        CursorCaptureSettings::Default,
-        DrawBorderSettings::Default,
-        ColorFormat::Rgba8,
-        None,
-    );
-
-    Capture::start(settings).unwrap(); // Start capture
+    if let Err(error) = canvas.play(source.as_mut()) {
+        eprintln!("{}", error);
+    }
 }
@@ -1,5 +1,7 @@
 use crossterm::style::Color;
 
+use crate::color::ColorSpace;
+
 /// Vector2
 #[derive(Clone, Copy, Default)]
 pub struct Vector2 {
@@ -123,14 +125,22 @@ impl PxData {
         self.b = b;
     }
 
+    // Color converted to another model, e.g. `pixel.to_space::<Hsv>()`
+    pub fn to_space<T: ColorSpace>(&self) -> T {
+        T::from_rgb(self.get_color_raw())
+    }
+
+    pub fn set_from_space<T: ColorSpace>(&mut self, color: &T) {
+        let (r, g, b) = color.to_rgb();
+
+        self.set_color_raw(r, g, b);
+    }
+
     pub fn set_color(&mut self, color: Color) {
-        match color {
-            Color::Rgb{r, g, b} => {
-                self.r = r;
-                self.g = g;
-                self.b = b;
-            }
-            _ => {}
+        if let Color::Rgb{r, g, b} = color {
+            self.r = r;
+            self.g = g;
+            self.b = b;
         }
     }
 
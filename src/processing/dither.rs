@@ -0,0 +1,313 @@
+use std::sync::OnceLock;
+
+// Side of the tileable blue noise mask
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Spread of the gaussian that measures how crowded the lit pixels around a spot are
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Error diffusion kernels, wider ones spread the error further for smoother gradients
+#[derive(Clone, Copy, PartialEq)]
+pub enum Diffusion {
+    FloydSteinberg,
+    // Diffuses only 3/4 of the error, more contrast and less noise in flat areas
+    Atkinson,
+    JarvisJudiceNinke,
+    Sierra,
+}
+
+impl Diffusion {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "floyd-steinberg" => Some(Diffusion::FloydSteinberg),
+            "atkinson" => Some(Diffusion::Atkinson),
+            "jjn" => Some(Diffusion::JarvisJudiceNinke),
+            "sierra" => Some(Diffusion::Sierra),
+            _ => None,
+        }
+    }
+
+    // Neighbours as (dx, dy, weight) for a left to right scan, and the divisor of the weights
+    fn kernel(&self) -> (&'static [(i32, i32, f32)], f32) {
+        match self {
+            Diffusion::FloydSteinberg => (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0),
+            Diffusion::Atkinson => (&[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)], 8.0),
+            Diffusion::JarvisJudiceNinke => (&[
+                (1, 0, 7.0), (2, 0, 5.0),
+                (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+                (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+            ], 48.0),
+            Diffusion::Sierra => (&[
+                (1, 0, 5.0), (2, 0, 3.0),
+                (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+                (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+            ], 32.0),
+        }
+    }
+}
+
+/// Threshold maps for ordered dithering. Thresholds only depend on the screen position, so
+/// static parts of a live capture keep exactly the same pattern from frame to frame.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Matrix {
+    // Side length, a power of two from 2 to 16
+    Bayer(u32),
+    // No visible grid, finer looking than Bayer at the same number of levels
+    BlueNoise,
+}
+
+impl Matrix {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bayer2" => Some(Matrix::Bayer(2)),
+            "bayer4" => Some(Matrix::Bayer(4)),
+            "bayer8" => Some(Matrix::Bayer(8)),
+            "bayer16" => Some(Matrix::Bayer(16)),
+            "blue-noise" => Some(Matrix::BlueNoise),
+            _ => None,
+        }
+    }
+
+    /// Threshold in 0..1 for a screen position, the map repeats across the screen
+    pub fn threshold(&self, x: u32, y: u32) -> f32 {
+        match self {
+            Matrix::Bayer(size) => {
+                let size = (*size).clamp(2, 16).next_power_of_two();
+                let bits = size.trailing_zeros();
+                let mut value = 0;
+
+                // The 2x2 pattern [[0, 2], [3, 1]] repeated at every scale, the lowest bits weigh the most
+                for bit in 0..bits {
+                    let cell = match ((x >> bit) & 1, (y >> bit) & 1) {
+                        (0, 0) => 0,
+                        (1, 0) => 2,
+                        (0, _) => 3,
+                        _ => 1,
+                    };
+
+                    value += cell << (2 * (bits - 1 - bit));
+                }
+
+                (value as f32 + 0.5) / (size * size) as f32
+            },
+            Matrix::BlueNoise => {
+                let index = (y as usize % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x as usize % BLUE_NOISE_SIZE;
+
+                blue_noise()[index]
+            },
+        }
+    }
+}
+
+// Index of the most crowded lit pixel, or the emptiest unlit one
+fn extreme(pattern: &[bool], energy: &[f32], lit: bool) -> usize {
+    let candidates = (0..pattern.len()).filter(|&index| pattern[index] == lit);
+    let extreme = match lit {
+        true => candidates.max_by(|a, b| energy[*a].total_cmp(&energy[*b])),
+        false => candidates.min_by(|a, b| energy[*a].total_cmp(&energy[*b])),
+    };
+
+    extreme.unwrap()
+}
+
+// Void and cluster (Ulichney 1993), ranks every pixel of the tile so the lit pixels at any
+// threshold are spread as evenly as possible. Built once, the first time it is needed.
+fn blue_noise() -> &'static [f32] {
+    static TILE: OnceLock<Vec<f32>> = OnceLock::new();
+
+    TILE.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE as i32;
+        let area = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let radius = (BLUE_NOISE_SIGMA * 4.0).ceil() as i32;
+        let update = |energy: &mut [f32], index: usize, sign: f32| {
+            let (x, y) = ((index % BLUE_NOISE_SIZE) as i32, (index / BLUE_NOISE_SIZE) as i32);
+
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let weight = (-((dx * dx + dy * dy) as f32) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp();
+                    let target = ((y + dy).rem_euclid(size) * size + (x + dx).rem_euclid(size)) as usize;
+
+                    energy[target] += sign * weight;
+                }
+            }
+        };
+        let mut pattern = vec![false; area];
+        let mut energy = vec![0.0f32; area];
+        let mut state: u32 = 0x9E37_79B9;
+        let mut seeded = 0;
+
+        // A tenth of the pixels lit at fixed pseudo random spots, so every run builds the same tile
+        while seeded < area / 10 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            let index = state as usize % area;
+
+            if !pattern[index] {
+                pattern[index] = true;
+                update(&mut energy, index, 1.0);
+                seeded += 1;
+            }
+        }
+
+        // Move the most crowded pixel into the largest gap until it has nowhere better to go
+        for _ in 0..area {
+            let cluster = extreme(&pattern, &energy, true);
+
+            pattern[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+
+            let void = extreme(&pattern, &energy, false);
+
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; area];
+        let (mut peeled, mut peeled_energy) = (pattern.clone(), energy.clone());
+
+        // Lowest ranks go to the initial pixels, most crowded first out
+        for rank in (0..seeded).rev() {
+            let cluster = extreme(&peeled, &peeled_energy, true);
+
+            peeled[cluster] = false;
+            update(&mut peeled_energy, cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+
+        // The rest fill the largest gap left, which past half full is also the tightest cluster of unlit pixels
+        for rank in seeded..area {
+            let void = extreme(&pattern, &energy, false);
+
+            pattern[void] = true;
+            update(&mut energy, void, 1.0);
+            ranks[void] = rank;
+        }
+
+        ranks.into_iter().map(|rank| (rank as f32 + 0.5) / area as f32).collect()
+    })
+}
+
+/// Either spreads quantization error to neighbours or compares against a fixed threshold map
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+    Diffusion(Diffusion),
+    Ordered(Matrix),
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Diffusion::from_name(name).map(Dither::Diffusion)
+            .or_else(|| Matrix::from_name(name).map(Dither::Ordered))
+    }
+}
+
+/// Visits a grid of colors in serpentine order, handing `pick` each color plus the error diffused
+/// onto it so far. `pick` returns the color actually shown and the difference moves on to the
+/// neighbours not visited yet. `None` entries are skipped and error aimed at them is dropped.
+pub fn diffuse(
+    colors: &[Option<[f32; 3]>],
+    width: usize,
+    diffusion: Diffusion,
+    mut pick: impl FnMut(usize, [f32; 3]) -> [f32; 3],
+) {
+    let width = width.max(1);
+    let height = colors.len().div_ceil(width);
+    let (kernel, divisor) = diffusion.kernel();
+    let mut errors = vec![[0.0f32; 3]; colors.len()];
+
+    for y in 0..height {
+        // Alternating direction keeps the error from piling up towards one side
+        let reverse = y % 2 == 1;
+
+        for step in 0..width {
+            let x = if reverse { width - 1 - step } else { step };
+            let index = y * width + x;
+            let color = match colors.get(index) {
+                Some(Some(color)) => *color,
+                _ => continue,
+            };
+            let wanted = [color[0] + errors[index][0], color[1] + errors[index][1], color[2] + errors[index][2]];
+            let actual = pick(index, wanted);
+            let error = [wanted[0] - actual[0], wanted[1] - actual[1], wanted[2] - actual[2]];
+
+            for &(dx, dy, weight) in kernel {
+                let target_x = x as i32 + if reverse { -dx } else { dx };
+                let target_y = y + dy as usize;
+
+                if target_x < 0 || target_x >= width as i32 || target_y >= height {
+                    continue;
+                }
+
+                let target = target_y * width + target_x as usize;
+
+                if target < colors.len() {
+                    for channel in 0..3 {
+                        errors[target][channel] += error[channel] * weight / divisor;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFFUSIONS: [Diffusion; 4] = [
+        Diffusion::FloydSteinberg, Diffusion::Atkinson, Diffusion::JarvisJudiceNinke, Diffusion::Sierra,
+    ];
+
+    #[test]
+    fn kernel_weights_add_up_to_the_divisor() {
+        for diffusion in DIFFUSIONS {
+            let (kernel, divisor) = diffusion.kernel();
+            let total: f32 = kernel.iter().map(|&(_, _, weight)| weight).sum();
+
+            // Atkinson drops a quarter of the error on purpose
+            match diffusion {
+                Diffusion::Atkinson => assert_eq!(total, divisor * 0.75),
+                _ => assert_eq!(total, divisor),
+            }
+        }
+    }
+
+    #[test]
+    fn flat_gray_lights_half_the_pixels() {
+        let colors = vec![Some([127.5; 3]); 32 * 32];
+        let mut lit = 0;
+
+        diffuse(&colors, 32, Diffusion::FloydSteinberg, |_, wanted| {
+            let value = if wanted[0] >= 127.5 { 255.0 } else { 0.0 };
+
+            lit += (value > 0.0) as usize;
+            [value; 3]
+        });
+
+        let coverage = lit as f32 / colors.len() as f32;
+
+        assert!((coverage - 0.5).abs() < 0.01, "{}", coverage);
+    }
+
+    #[test]
+    fn odd_rows_run_right_to_left() {
+        // Only the last pixel of the second row has anything to diffuse
+        let mut colors = vec![Some([0.0; 3]); 6];
+        let mut visited = Vec::new();
+
+        colors[5] = Some([16.0; 3]);
+        diffuse(&colors, 3, Diffusion::FloydSteinberg, |index, wanted| {
+            visited.push((index, wanted[0]));
+            [0.0; 3]
+        });
+
+        // 7/16 of the error goes to the next pixel in scan order, which is the one to the left
+        assert_eq!(visited, [(0, 0.0), (1, 0.0), (2, 0.0), (5, 16.0), (4, 7.0), (3, 49.0 / 16.0)]);
+    }
+}
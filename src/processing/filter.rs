@@ -1,12 +1,62 @@
 use crossterm::style::Color;
-use crate::{frame::{Size, Image, FrameData}, pixel::{PxData, Vector2}, pixel, vector2};
+use crate::{color, frame::{Size, Image, FrameData}, pixel::{PxData, Vector2}, pixel, vector2};
 
-use super::{Energy, Kernel, Scaling};
+use super::{dither::{Diffusion, Matrix}, resample, Energy, Kernel, ImageProcess, Scaling};
 
-trait Filter {
+/// A processing step the canvas runs on every scaled frame, in the order they were added
+pub trait Filter {
     fn apply(&mut self, frame_data: &mut FrameData);
 }
 
+// Filter for a `--filter` argument, `name` or `name=value`
+pub fn from_spec(spec: &str) -> Option<Box<dyn Filter>> {
+    let (name, value) = spec.split_once('=').unwrap_or((spec, ""));
+    // `<shades>[,<floyd-steinberg|atkinson|jjn|sierra>]`, with a kernel the rounding error is diffused
+    let diffused = |minimum: u8| {
+        let (shades, diffusion) = match value.split_once(',') {
+            Some((shades, diffusion)) => (shades, Some(Diffusion::from_name(diffusion)?)),
+            None => (value, None),
+        };
+
+        Some((shades.parse::<u8>().ok().filter(|shades| *shades >= minimum)?, diffusion))
+    };
+    let amount = || value.parse::<f32>().ok().filter(|amount| amount.is_finite());
+
+    match name {
+        "flip-horizontal" => Some(Box::new(Flip { horizontal: true })),
+        "flip-vertical" => Some(Box::new(Flip { horizontal: false })),
+        // Steps above black per channel
+        "quantize" => {
+            let (shades, diffusion) = diffused(1)?;
+
+            Some(Box::new(Quantize { shades, diffusion }))
+        },
+        // Gray levels counting black and white
+        "grayscale" => {
+            let (shades, diffusion) = diffused(2)?;
+
+            Some(Box::new(Grayscale { shades, diffusion }))
+        },
+        // `ordered-dither=<bayer2-16|blue-noise>,<shades>`
+        "ordered-dither" => {
+            let (matrix, value) = value.split_once(',')?;
+            let shades = value.parse::<u8>().ok().filter(|shades| *shades > 0)?;
+
+            Some(Box::new(OrderedDither { matrix: Matrix::from_name(matrix)?, shades }))
+        },
+        "hue-rotate" => Some(Box::new(HueRotate { degrees: amount()? })),
+        "saturation" => Some(Box::new(Saturation { factor: amount()? })),
+        "vibrance" => Some(Box::new(Vibrance { amount: amount()? })),
+        "lightness" => Some(Box::new(Lightness { amount: amount()? })),
+        "gradient" => match value {
+            "" | "sobel" => Some(Box::new(GradientMagnitude { kernel: Kernel::Sobel })),
+            "prewitt" => Some(Box::new(GradientMagnitude { kernel: Kernel::Prewitt })),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 pub struct Flip {
     pub horizontal: bool
 }
@@ -17,7 +67,7 @@ impl Filter for Flip {
         let size: Size = image.size;
         let pixel_data = image.get_pixel_data();
         
-        match pixel_data.len() > 0 && size.width > 0 && size.height > 0 {
+        match !pixel_data.is_empty() && size.width > 0 && size.height > 0 {
             true => {
                 let mut flipped_data = vec![PxData::default(); pixel_data.len()];
                 let width = size.width;
@@ -43,43 +93,47 @@ impl Filter for Flip {
 }
 
 pub struct Quantize {
-    pub shades: u8
+    pub shades: u8,
+    pub diffusion: Option<Diffusion>,
 }
 
 impl Filter for Quantize {
+    fn apply(&mut self, frame_data: &mut FrameData) {
+        frame_data.get_image_mut().quantize(self.shades, self.diffusion);
+    }
+}
+
+/// Quantize against a threshold map instead of spreading error, stable on live capture
+pub struct OrderedDither {
+    pub matrix: Matrix,
+    pub shades: u8,
+}
+
+impl Filter for OrderedDither {
     fn apply(&mut self, frame_data: &mut FrameData) {
         let image = frame_data.get_image_mut();
+        // One quantization step of offset, centred so the average brightness stays put
+        let step = 255.0 / self.shades.max(1) as f32;
 
         for pixel in image.get_pixel_data_mut().iter_mut() {
+            let offset = (self.matrix.threshold(pixel.get_x(), pixel.get_y()) - 0.5) * step;
+            let (r, g, b) = pixel.get_color_raw();
+            let channel = |value: u8| (value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+
+            pixel.set_color_raw(channel(r), channel(g), channel(b));
             pixel.quantize(self.shades);
         }
     }
 }
 
 pub struct Grayscale {
-    pub shades: u8
+    pub shades: u8,
+    pub diffusion: Option<Diffusion>,
 }
 
 impl Filter for Grayscale {
     fn apply(&mut self, frame_data: &mut FrameData) {
-        let image = frame_data.get_image_mut();
-
-        match self.shades >= 2 {
-            true => {
-                let factor = 255 / (self.shades - 1) as u8;
-
-                for pixel in image.get_pixel_data_mut().iter_mut() {
-                    let (r, g, b) = pixel.get_color_raw();
-                    let average = (r as f64 + g as f64 + b as f64) / 3.0;
-                    let gray = ((average / factor as f64).ceil() * factor as f64) as u8;
-
-                    pixel.set_color_raw(gray , gray, gray);
-                }
-            },
-            false => {
-                panic!("Shades must be between 2 and 255")
-            }
-        }
+        frame_data.get_image_mut().grayscale(self.shades, self.diffusion);
     }
 }
 
@@ -95,6 +149,7 @@ impl Filter for Scale {
 
         let (width, height) = (self.size.width, self.size.height);
         let (buffer_width, buffer_height) = (image_size.width, image_size.height);
+        let space = image.get_blend_space();
         let mut resized_pixel_data = Vec::new();
 
         match self.scaling {
@@ -112,30 +167,26 @@ impl Filter for Scale {
                 image.pixel_data = resized_pixel_data;
             },
             Scaling::Bilinear => { //https://x-engineer.org/bilinear-interpolation/
+                // Weighted by the offset from the top left sample, the right and bottom edges have no second sample
                 macro_rules! bilinear_interpolate {
-                    ($x:expr, $y:expr, $x1:expr, $y1:expr, $x2:expr, $y2:expr, $q11:expr, $q21:expr, $q12:expr, $q22:expr) => {{
-                        let x1 = $x1 as f64;
-                        let x2 = $x2 as f64;
-                        let y1 = $y1 as f64;
-                        let y2 = $y2 as f64;
-
-                        let r1 = ($q11 as f64 * (x2 - $x) + $q21 as f64 * ($x - x1)) / (x2 - x1);
-                        let r2 = ($q12 as f64 * (x2 - $x) + $q22 as f64 * ($x - x1)) / (x2 - x1);
-                        let p = (r1 * (y2 - $y) + r2 * ($y - y1)) / (y2 - y1);
+                    ($fx:expr, $fy:expr, $q11:expr, $q21:expr, $q12:expr, $q22:expr) => {{
+                        let r1 = space.decode($q11) * (1.0 - $fx) + space.decode($q21) * $fx;
+                        let r2 = space.decode($q12) * (1.0 - $fx) + space.decode($q22) * $fx;
 
-                        p.round() as u8
+                        space.encode(r1 * (1.0 - $fy) + r2 * $fy)
                     }};
                 }
 
                 for y in 0..height {
                     for x in 0..width {
-                        let src_x = (x as f64 * (buffer_width - 1) as f64 / (width - 1) as f64) as f64;
-                        let src_y = (y as f64 * (buffer_height - 1) as f64 / (height - 1) as f64) as f64;
+                        let src_x = x as f64 * (buffer_width - 1) as f64 / (width - 1).max(1) as f64;
+                        let src_y = y as f64 * (buffer_height - 1) as f64 / (height - 1).max(1) as f64;
                         let x1 = src_x.floor() as u32;
                         let x2 = (x1 + 1).min(buffer_width - 1);
                         let y1 = src_y.floor() as u32;
                         let y2 = (y1 + 1).min(buffer_height - 1);
-                        
+                        let (fx, fy) = ((src_x - x1 as f64) as f32, (src_y - y1 as f64) as f32);
+
                         let index11 = (y1 * buffer_width + x1) as usize;
                         let index21 = (y1 * buffer_width + x2) as usize;
                         let index12 = (y2 * buffer_width + x1) as usize;
@@ -146,11 +197,11 @@ impl Filter for Scale {
                         let q22 = image.get_pixel_data()[index22];
 
                         let r = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_r(), q21.get_r(), q12.get_r(), q22.get_r());
+                            fx, fy, q11.get_r(), q21.get_r(), q12.get_r(), q22.get_r());
                         let g = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_g(), q21.get_g(), q12.get_g(), q22.get_g());
+                            fx, fy, q11.get_g(), q21.get_g(), q12.get_g(), q22.get_g());
                         let b = bilinear_interpolate!(
-                            src_x, src_y, x1, y1, x2, y2, q11.get_b(), q21.get_b(), q12.get_b(), q22.get_b());
+                            fx, fy, q11.get_b(), q21.get_b(), q12.get_b(), q22.get_b());
 
                         let new_pixel = pixel!(r, g, b, x, y);
                         resized_pixel_data.push(new_pixel);
@@ -160,13 +211,56 @@ impl Filter for Scale {
                 image.set_image_size(self.size);
                 image.pixel_data = resized_pixel_data;
             },
-            _ => {
-                panic!("Invalid scaling type");
-            }
+            Scaling::Bicubic | Scaling::Lanczos(_) | Scaling::Area => {
+                let resized_pixel_data = resample::resample(image.get_pixel_data(), image_size, self.size, &self.scaling, space);
+
+                image.set_image_size(self.size);
+                image.pixel_data = resized_pixel_data;
+            },
         }
     }
 }
 
+pub struct HueRotate {
+    pub degrees: f32
+}
+
+impl Filter for HueRotate {
+    fn apply(&mut self, frame_data: &mut FrameData) {
+        frame_data.get_image_mut().hue_rotate(self.degrees);
+    }
+}
+
+pub struct Saturation {
+    pub factor: f32
+}
+
+impl Filter for Saturation {
+    fn apply(&mut self, frame_data: &mut FrameData) {
+        frame_data.get_image_mut().saturation(self.factor);
+    }
+}
+
+pub struct Vibrance {
+    pub amount: f32
+}
+
+impl Filter for Vibrance {
+    fn apply(&mut self, frame_data: &mut FrameData) {
+        frame_data.get_image_mut().vibrance(self.amount);
+    }
+}
+
+pub struct Lightness {
+    pub amount: f32
+}
+
+impl Filter for Lightness {
+    fn apply(&mut self, frame_data: &mut FrameData) {
+        frame_data.get_image_mut().lightness(self.amount);
+    }
+}
+
 pub struct GradientMagnitude {
     pub kernel: Kernel
 }
@@ -187,57 +281,33 @@ impl Filter for GradientMagnitude {
     }
 }
 
-struct MaskOntop {
-    other: Image,
-    color_mask: Color,
-    threshold: u8
+pub struct MaskOntop {
+    pub other: Image,
+    pub color_mask: Color,
+    pub threshold: u8
 }
 
 impl Filter for MaskOntop {
+    // Luminance is compared in the frame's blend space
     fn apply(&mut self, frame_data: &mut FrameData) {
-        let size = frame_data.get_image().get_image_size();
-        let mut new_pixel_data = Vec::new();
-        let (width, height) = (size.width, size.height);
-        let image = frame_data.get_image_mut();
-
-        for y in 0..height {
-            for x in 0..width {
-                let pixel = image.get_pixel(vector2!(x, y));
-                let other_pixel = self.other.get_pixel(vector2!(x, y));
-                let (r, g, b) = pixel.get_color_raw();
-                let (r2, g2, b2) = other_pixel.get_color_raw();
-                let average = ((r as i32 + g as i32 + b as i32) / 3) as u8;
-                let average2 = ((r2 as i32 + g2 as i32 + b2 as i32) / 3) as u8;
-                let diff = (average as i32 - average2 as i32).abs() as u8;
-
-                if diff > self.threshold {
-                    new_pixel_data.push(pixel);
-                } else {
-                    new_pixel_data.push(other_pixel);
-                }
-            }
-        }
-
-        image.pixel_data = new_pixel_data;
+        frame_data.get_image_mut().mask_ontop(&self.other, self.color_mask, self.threshold);
     }
 }
 
-struct Ascii {
-    shades: String
+pub struct Ascii {
+    pub shades: String
 }
 
 impl Filter for Ascii {
     fn apply(&mut self, frame_data: &mut FrameData) {
         let image = frame_data.get_image_mut();
 
-        let mut ascii_image = String::new();
         let shades = self.shades.chars().collect::<Vec<char>>();
         let shades_len = shades.len() as f64;
         let pixel_data = image.get_pixel_data_mut();
 
         for pixel in pixel_data.iter_mut() {
-            let (r, g, b) = pixel.get_color_raw();
-            let average = ((r as f64 + g as f64 + b as f64) / 3.0) as f64;
+            let average = color::luminance(pixel.get_color_raw()) as f64;
             let shade_index = (average / 255.0 * shades_len).floor() as usize;
             let shade = shades[shade_index.min(shades_len as usize - 1)];
 
@@ -246,6 +316,105 @@ impl Filter for Ascii {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::frame::ColorFormat;
+    use crate::source::pattern::{Pattern, PatternSource};
+    use super::*;
+
+    fn gray_frame(value: u8) -> FrameData {
+        let size = Size { width: 16, height: 16 };
+        let mut frame_data = FrameData::default();
+
+        frame_data.set_color_format(ColorFormat::Rgb8);
+        frame_data.set_raw_data(vec![value; (size.width * size.height * 3) as usize], size);
+        frame_data
+    }
+
+    #[test]
+    fn parses_specs() {
+        assert!(from_spec("flip-horizontal").is_some());
+        assert!(from_spec("quantize=4").is_some());
+        assert!(from_spec("ordered-dither=bayer8,2").is_some());
+        assert!(from_spec("ordered-dither=blue-noise,16").is_some());
+        assert!(from_spec("gradient").is_some());
+        assert!(from_spec("hue-rotate=-90").is_some());
+        assert!(from_spec("saturation=1.5").is_some());
+        assert!(from_spec("saturation").is_none());
+        assert!(from_spec("lightness=nan").is_none());
+        assert!(from_spec("ordered-dither=bayer8").is_none());
+        assert!(from_spec("ordered-dither=bayer3,2").is_none());
+        assert!(from_spec("quantize=0").is_none());
+        assert!(from_spec("quantize=4,atkinson").is_some());
+        assert!(from_spec("quantize=4,bayer4").is_none());
+        assert!(from_spec("grayscale=2,sierra").is_some());
+        assert!(from_spec("grayscale=1").is_none());
+        assert!(from_spec("sharpen").is_none());
+    }
+
+    #[test]
+    fn diffused_grayscale_keeps_the_average() {
+        let mut frame_data = gray_frame(128);
+
+        from_spec("grayscale=2,floyd-steinberg").unwrap().apply(&mut frame_data);
+
+        let pixels = frame_data.get_image().get_pixel_data();
+        let white = pixels.iter().filter(|pixel| pixel.get_color_raw() == (255, 255, 255)).count();
+        let black = pixels.iter().filter(|pixel| pixel.get_color_raw() == (0, 0, 0)).count();
+
+        assert_eq!(white + black, pixels.len());
+        assert!(white.abs_diff(pixels.len() / 2) <= 4, "{} white", white);
+    }
+
+    #[test]
+    fn ordered_dither_keeps_the_average() {
+        let mut frame_data = gray_frame(128);
+
+        // One step between black and white
+        from_spec("ordered-dither=bayer4,1").unwrap().apply(&mut frame_data);
+
+        let pixels = frame_data.get_image().get_pixel_data();
+        let white = pixels.iter().filter(|pixel| pixel.get_color_raw() == (255, 255, 255)).count();
+        let black = pixels.iter().filter(|pixel| pixel.get_color_raw() == (0, 0, 0)).count();
+
+        assert_eq!(white + black, pixels.len());
+        assert_eq!(white, pixels.len() / 2);
+    }
+
+    #[test]
+    fn color_filters_chain() {
+        let mut frame_data = gray_frame(0);
+
+        frame_data.get_image_mut().get_pixel_data_mut()[0].set_color_raw(200, 40, 40);
+
+        for spec in ["saturation=0", "lightness=0.2"] {
+            from_spec(spec).unwrap().apply(&mut frame_data);
+        }
+
+        let pixels = frame_data.get_image().get_pixel_data();
+        let (r, g, b) = pixels[0].get_color_raw();
+
+        // Desaturated red is a gray, then both it and black get lighter
+        assert!(r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1, "{:?}", (r, g, b));
+        assert!(pixels[1].get_r() > 0);
+        assert!(r > pixels[1].get_r());
+    }
+
+    #[test]
+    fn ordered_dither_is_stable() {
+        let source = PatternSource::new(Pattern::Gradient, Size { width: 32, height: 8 }, 0.0);
+        let mut first = source.frame_data(0);
+        let mut second = source.frame_data(1);
+        let mut filter = OrderedDither { matrix: Matrix::BlueNoise, shades: 4 };
+
+        filter.apply(&mut first);
+        filter.apply(&mut second);
+
+        assert!(first.get_image().get_pixel_data().iter().zip(second.get_image().get_pixel_data())
+            .all(|(a, b)| a.get_color_raw() == b.get_color_raw()));
+    }
+}
+
 /*
 / Direction: true = horizontal, false = vertical
     fn seam_carve(&mut self, direction: bool, remove: bool) -> &mut Self {
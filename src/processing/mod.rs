@@ -1,11 +1,35 @@
 use crossterm::style::Color;
-use crate::{frame::{Size, Image, FrameData}, pixel::{PxData, Vector2}, pixel, vector2};
-mod filter;
+use dither::{Diffusion, Dither};
+use palette::PaletteMatcher;
+use crate::{color, frame::{Size, Image}, pixel::{PxData, Vector2}, pixel};
+pub mod dither;
+pub mod filter;
+pub mod palette;
+pub mod resample;
+
+#[derive(Clone, Copy)]
 pub enum Scaling {
     Nearest,
     Bilinear,
-    //Bicubic,
-    //Lanczos,
+    Bicubic,
+    // Window size, 3 is the usual choice
+    Lanczos(u32),
+    // Average of every source pixel under the destination pixel, for large reductions
+    Area,
+}
+
+impl Scaling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Scaling::Nearest),
+            "bilinear" => Some(Scaling::Bilinear),
+            "bicubic" => Some(Scaling::Bicubic),
+            "lanczos2" => Some(Scaling::Lanczos(2)),
+            "lanczos3" => Some(Scaling::Lanczos(3)),
+            "area" => Some(Scaling::Area),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -21,14 +45,21 @@ pub struct Energy {
 
 pub trait ImageProcess {
     fn flip(&mut self, horizonal: bool, vertical: bool) -> &mut Self;
-    fn quantize(&mut self, shades: u8) -> &mut Self;
-    fn grayscale(&mut self, shades: u8) -> &mut Self;
+    fn quantize(&mut self, shades: u8, diffusion: Option<Diffusion>) -> &mut Self;
+    fn grayscale(&mut self, shades: u8, diffusion: Option<Diffusion>) -> &mut Self;
+    fn remap(&mut self, matcher: &mut PaletteMatcher, dither: Option<Dither>) -> &mut Self;
     fn scale(&mut self, size: Size, scaling: Scaling, original_size: Size) -> &mut Self;
     fn seam_carve(&mut self, direction: bool, remove: bool) -> &mut Self;
     fn gradient_magnitude(&mut self, kernel: Kernel) -> &mut Self;
     fn mask_ontop(&mut self, other: &Image, color_mask: Color, threshold: u8) -> &mut Self;
     fn get_ascii(&self, shades: String) -> String;
     fn brightness(&mut self, value: i32) -> &mut Self;
+    fn hue_rotate(&mut self, degrees: f32) -> &mut Self;
+    fn saturation(&mut self, factor: f32) -> &mut Self;
+    fn vibrance(&mut self, amount: f32) -> &mut Self;
+    fn lightness(&mut self, amount: f32) -> &mut Self;
+    fn letterbox(&mut self, size: Size, background: Color) -> &mut Self;
+    fn crop(&mut self, size: Size) -> &mut Self;
 }
 
 pub struct Threshold {
@@ -59,10 +90,7 @@ impl Energy {
             for x in 0..width {
                 let index = (y * width + x) as usize;
                 let pixel = pixel_data[index];
-                let (r, g, b) = pixel.get_color_raw();
-                let average = ((r as i32 + g as i32 + b as i32) / 3);
-
-                grayscale_map[y as usize][x as usize] = average;
+                grayscale_map[y as usize][x as usize] = color::luminance(pixel.get_color_raw()) as i32;
             }
         }
 
@@ -111,7 +139,7 @@ impl Energy {
         }
     }
 
-    fn compute_gradient(&self, pixel: i32, x: usize, y: usize) -> (i32, i32) {
+    fn compute_gradient(&self, x: usize, y: usize) -> (i32, i32) {
         let (kx, ky) = self.get_kernel_matrix();
         let mut gx = 0;
         let mut gy = 0;
@@ -142,12 +170,12 @@ impl Energy {
     pub fn compute_gradient_magnitude(&mut self) {
         let mut result = vec![vec![0; self.pixels[0].len()]; self.pixels.len()];
 
-        for i in 0..self.pixels.len() {
-            for j in 0..self.pixels[0].len() {
-                let (gx, gy) = self.compute_gradient(self.pixels[i][j], j, i);
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let (gx, gy) = self.compute_gradient(j, i);
                 let magnitude = ((gx.pow(2) + gy.pow(2)) as f64).sqrt();
 
-                result[i][j] = magnitude as i32;
+                *value = magnitude as i32;
             }
         }
 
@@ -216,12 +244,12 @@ impl Energy {
             result.push(min_index);
             let mut new_energy_map = Vec::new();
 
-            for i in 0..energy_map.len() {
+            for (i, energy_row) in energy_map.iter().enumerate() {
                 let mut row = Vec::new();
 
-                for j in 0..energy_map[0].len() {
+                for (j, &energy) in energy_row.iter().enumerate() {
                     if j != seam[i] {
-                        row.push(energy_map[i][j]);
+                        row.push(energy);
                     }
                 }
 
@@ -235,7 +263,7 @@ impl Energy {
     }
 
     // If scale is true, then the missing pixels will be interpolated
-    pub fn remove_seams(&mut self, seams: Vec<usize>, scale: bool) {
+    pub fn remove_seams(&mut self, seams: Vec<usize>, _scale: bool) {
         let mut result = Vec::new();
 
         for i in 0..self.pixels.len() {
@@ -244,7 +272,7 @@ impl Energy {
             let mut index = 0;
 
             for j in 0..self.pixels[0].len() {
-                if seam.len() > 0 && seam[0] == j {
+                if !seam.is_empty() && seam[0] == j {
                     seam.remove(0);
                 } else {
                     row.push(self.pixels[i][index]);
@@ -260,21 +288,17 @@ impl Energy {
     pub fn add_seams(&mut self, seams: Vec<Vec<usize>>) {
         let mut result = Vec::new();
 
-        for i in 0..self.pixels.len() {
+        for (pixels, mut seam) in self.pixels.iter().zip(seams) {
             let mut row = Vec::new();
-            let mut seam = seams[i].clone();
-            let mut index = 0;
 
-            for j in 0..self.pixels[0].len() {
-                if seam.len() > 0 && seam[0] == j {
-                    row.push(self.pixels[i][index]);
-                    row.push(self.pixels[i][index]);
+            for (j, &pixel) in pixels.iter().enumerate() {
+                if !seam.is_empty() && seam[0] == j {
+                    row.push(pixel);
+                    row.push(pixel);
                     seam.remove(0);
                 } else {
-                    row.push(self.pixels[i][index]);
+                    row.push(pixel);
                 }
-
-                index += 1;
             }
 
             result.push(row);
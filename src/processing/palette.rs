@@ -0,0 +1,603 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::color::Oklab;
+use crate::frame::Image;
+use super::{dither::Dither, ImageProcess};
+
+// Pixels looked at when building a palette, spread evenly over the image
+const SAMPLE_LIMIT: usize = 8192;
+
+// Levels of the color octree, 6 bits per channel is plenty before merging
+const OCTREE_DEPTH: usize = 6;
+
+// Assignment rounds k-means runs per frame, it starts from a good guess so a few are enough
+const KMEANS_ITERATIONS: u32 = 4;
+
+// Share of the previous palette kept in every new one
+const DEFAULT_SMOOTHING: f32 = 0.5;
+
+// Palette entry as RGB bytes
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Quantizer {
+    // Splits the widest box of colors at its median until there are enough boxes
+    MedianCut,
+    // Merges the least used leaves of a color octree
+    Octree,
+    // Refines median cut centers, or the previous palette, by repeated nearest assignment
+    KMeans,
+}
+
+impl Quantizer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "median-cut" => Some(Quantizer::MedianCut),
+            "octree" => Some(Quantizer::Octree),
+            "kmeans" => Some(Quantizer::KMeans),
+            _ => None,
+        }
+    }
+}
+
+/// Builds an N color palette for every frame. With smoothing each entry drifts towards the new
+/// frame's colors instead of jumping, so live capture doesn't shimmer as the palette changes.
+pub struct PaletteBuilder {
+    quantizer: Quantizer,
+    colors: usize,
+    smoothing: f32,
+    previous: Vec<[f32; 3]>,
+}
+
+impl PaletteBuilder {
+    // `colors` is capped at 256 so entries fit palette registers and `PaletteMatcher` indices
+    pub fn new(quantizer: Quantizer, colors: usize) -> Self {
+        Self {
+            quantizer,
+            colors: colors.clamp(1, 256),
+            smoothing: DEFAULT_SMOOTHING,
+            previous: Vec::new(),
+        }
+    }
+
+    // 0 uses every frame's palette as is, closer to 1 holds on to the previous one longer
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    pub fn build(&mut self, image: &Image) -> Vec<(u8, u8, u8)> {
+        let pixel_data = image.get_pixel_data();
+        let step = (pixel_data.len() / SAMPLE_LIMIT).max(1);
+        let samples: Vec<[f32; 3]> = pixel_data.iter()
+            .step_by(step)
+            .map(|pixel| {
+                let (r, g, b) = pixel.get_color_raw();
+
+                [r as f32, g as f32, b as f32]
+            })
+            .collect();
+
+        if !samples.is_empty() {
+            let fresh = match self.quantizer {
+                Quantizer::MedianCut => median_cut(&samples, self.colors),
+                Quantizer::Octree => octree(&samples, self.colors),
+                Quantizer::KMeans => match self.previous.len() == self.colors {
+                    true => kmeans(&samples, self.previous.clone()),
+                    false => kmeans(&samples, median_cut(&samples, self.colors)),
+                },
+            };
+
+            self.previous = smooth(&self.previous, &fresh, self.smoothing);
+        }
+
+        self.previous.iter()
+            .map(|color| (color[0].round() as u8, color[1].round() as u8, color[2].round() as u8))
+            .collect()
+    }
+}
+
+fn distance_squared(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn mean(colors: &[[f32; 3]]) -> [f32; 3] {
+    let mut sum = [0.0; 3];
+
+    for color in colors {
+        for channel in 0..3 {
+            sum[channel] += color[channel];
+        }
+    }
+
+    sum.map(|value| value / colors.len().max(1) as f32)
+}
+
+// Every previous entry moves towards a fresh entry so entries keep their index, closest pairs are matched first.
+// Quantizers don't always return the same number of colors: previous entries left without a partner follow
+// their closest fresh entry, and fresh entries left over are added as they are.
+fn smooth(previous: &[[f32; 3]], fresh: &[[f32; 3]], smoothing: f32) -> Vec<[f32; 3]> {
+    let mut pairs: Vec<(f32, usize, usize)> = Vec::with_capacity(previous.len() * fresh.len());
+    let mut partner = vec![None; previous.len()];
+    let mut taken = vec![false; fresh.len()];
+
+    for (old_index, old) in previous.iter().enumerate() {
+        for (index, color) in fresh.iter().enumerate() {
+            pairs.push((distance_squared(old, color), old_index, index));
+        }
+    }
+
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    for (_, old_index, index) in pairs {
+        if partner[old_index].is_none() && !taken[index] {
+            partner[old_index] = Some(index);
+            taken[index] = true;
+        }
+    }
+
+    let mut palette: Vec<[f32; 3]> = previous.iter().zip(partner)
+        .map(|(old, partner)| {
+            let index = partner.unwrap_or_else(|| (0..fresh.len())
+                .min_by(|a, b| distance_squared(old, &fresh[*a]).total_cmp(&distance_squared(old, &fresh[*b])))
+                .unwrap());
+
+            std::array::from_fn(|channel| old[channel] * smoothing + fresh[index][channel] * (1.0 - smoothing))
+        })
+        .collect();
+
+    palette.extend(fresh.iter().zip(taken).filter(|(_, taken)| !taken).map(|(color, _)| *color));
+    palette
+}
+
+fn median_cut(samples: &[[f32; 3]], colors: usize) -> Vec<[f32; 3]> {
+    let mut boxes = vec![samples.to_vec()];
+
+    while boxes.len() < colors {
+        // Widest channel of every box, weighted by how many samples it holds
+        let (index, channel, score) = boxes.iter()
+            .enumerate()
+            .map(|(index, colors)| {
+                let (channel, range) = (0..3)
+                    .map(|channel| {
+                        let values = colors.iter().map(|color| color[channel]);
+                        let range = values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min);
+
+                        (channel, range)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap();
+
+                (index, channel, range * (colors.len() as f32).sqrt())
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+
+        // Every box is a single color, splitting further gains nothing
+        if score <= 0.0 {
+            break;
+        }
+
+        let mut lower = boxes.swap_remove(index);
+
+        lower.sort_by(|a, b| a[channel].total_cmp(&b[channel]));
+
+        let upper = lower.split_off(lower.len() / 2);
+
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    level: usize,
+    // Totals of every sample below the node, a merged node already holds its children's colors
+    count: u32,
+    sum: [f32; 3],
+}
+
+fn octree(samples: &[[f32; 3]], colors: usize) -> Vec<[f32; 3]> {
+    let mut nodes = vec![OctreeNode { children: [None; 8], level: 0, count: 0, sum: [0.0; 3] }];
+    let mut leaves = 0;
+
+    for sample in samples {
+        let mut node = 0;
+        let channels = sample.map(|value| value as u8);
+
+        for level in 0..=OCTREE_DEPTH {
+            nodes[node].count += 1;
+
+            for (sum, value) in nodes[node].sum.iter_mut().zip(sample) {
+                *sum += value;
+            }
+
+            if level == OCTREE_DEPTH {
+                break;
+            }
+
+            let bit = 7 - level;
+            let child = (((channels[0] >> bit) & 1) << 2 | ((channels[1] >> bit) & 1) << 1 | ((channels[2] >> bit) & 1)) as usize;
+
+            node = match nodes[node].children[child] {
+                Some(child) => child,
+                None => {
+                    nodes.push(OctreeNode { children: [None; 8], level: level + 1, count: 0, sum: [0.0; 3] });
+                    nodes[node].children[child] = Some(nodes.len() - 1);
+
+                    if level + 1 == OCTREE_DEPTH {
+                        leaves += 1;
+                    }
+
+                    nodes.len() - 1
+                }
+            };
+        }
+    }
+
+    let mut reducible: Vec<Vec<usize>> = vec![Vec::new(); OCTREE_DEPTH];
+
+    for (index, node) in nodes.iter().enumerate() {
+        if node.level < OCTREE_DEPTH {
+            reducible[node.level].push(index);
+        }
+    }
+
+    // Deepest level first, so a merged node's children are always leaves
+    while leaves > colors {
+        let level = match reducible.iter().rposition(|level| !level.is_empty()) {
+            Some(level) => level,
+            None => break,
+        };
+        let position = (0..reducible[level].len())
+            .min_by_key(|&position| nodes[reducible[level][position]].count)
+            .unwrap();
+        let index = reducible[level].swap_remove(position);
+        let children = nodes[index].children.iter().filter(|child| child.is_some()).count();
+
+        nodes[index].children = [None; 8];
+        leaves = leaves + 1 - children;
+    }
+
+    let mut palette = Vec::new();
+    let mut stack = vec![0];
+
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+
+        match node.children.iter().any(|child| child.is_some()) {
+            true => stack.extend(node.children.iter().flatten()),
+            false => palette.push(node.sum.map(|value| value / node.count.max(1) as f32)),
+        }
+    }
+
+    palette
+}
+
+fn kmeans(samples: &[[f32; 3]], mut centers: Vec<[f32; 3]>) -> Vec<[f32; 3]> {
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![([0.0f32; 3], 0u32); centers.len()];
+
+        for sample in samples {
+            let nearest = (0..centers.len())
+                .min_by(|a, b| distance_squared(sample, &centers[*a]).total_cmp(&distance_squared(sample, &centers[*b])))
+                .unwrap();
+
+            for (sum, value) in sums[nearest].0.iter_mut().zip(sample) {
+                *sum += value;
+            }
+
+            sums[nearest].1 += 1;
+        }
+
+        // Centers nothing was assigned to stay where they are
+        for (center, (sum, count)) in centers.iter_mut().zip(sums) {
+            if count > 0 {
+                *center = sum.map(|value| value / count as f32);
+            }
+        }
+    }
+
+    centers
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Metric {
+    // Plain euclidean distance on sRGB bytes, cheapest
+    Rgb,
+    // RGB weighted by how the eye's sensitivity shifts with red, https://www.compuphase.com/cmetric.htm
+    Redmean,
+    // Euclidean distance in OKLab, closest to how different two colors look
+    Oklab,
+}
+
+impl Metric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(Metric::Rgb),
+            "redmean" => Some(Metric::Redmean),
+            "oklab" => Some(Metric::Oklab),
+            _ => None,
+        }
+    }
+
+    // Color in the space distances are measured in
+    fn point(&self, color: (u8, u8, u8)) -> [f32; 3] {
+        match self {
+            Metric::Rgb | Metric::Redmean => [color.0 as f32, color.1 as f32, color.2 as f32],
+            Metric::Oklab => {
+                let oklab = Oklab::from_rgb(color);
+
+                [oklab.l, oklab.a, oklab.b]
+            },
+        }
+    }
+
+    fn distance(&self, a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        match self {
+            Metric::Redmean => {
+                let mean_red = (a[0] + b[0]) / 2.0;
+                let (dr, dg, db) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+
+                (2.0 + mean_red / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - mean_red) / 256.0) * db * db
+            },
+            _ => distance_squared(a, b),
+        }
+    }
+}
+
+/// Nearest palette entry under a distance metric, remembering colors it has already matched
+pub struct PaletteMatcher {
+    palette: Vec<(u8, u8, u8)>,
+    metric: Metric,
+    points: Vec<[f32; 3]>,
+    cache: HashMap<(u8, u8, u8), u8>,
+    spread: OnceCell<f32>,
+}
+
+impl PaletteMatcher {
+    // At most 256 entries, indices are bytes
+    pub fn new(palette: Vec<(u8, u8, u8)>, metric: Metric) -> Self {
+        let points = palette.iter().map(|&color| metric.point(color)).collect();
+
+        Self {
+            palette,
+            metric,
+            points,
+            cache: HashMap::new(),
+            spread: OnceCell::new(),
+        }
+    }
+
+    pub fn color(&self, index: u8) -> (u8, u8, u8) {
+        self.palette[index as usize]
+    }
+
+    pub fn nearest(&mut self, color: (u8, u8, u8)) -> u8 {
+        if let Some(&index) = self.cache.get(&color) {
+            return index;
+        }
+
+        let target = self.metric.point(color);
+        let index = self.points.iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| self.metric.distance(a, &target).total_cmp(&self.metric.distance(b, &target)))
+            .map(|(index, _)| index as u8)
+            .unwrap_or(0);
+
+        // Live capture throws a lot of distinct colors at this, keep the cache from growing forever
+        if self.cache.len() > 1 << 16 {
+            self.cache.clear();
+        }
+
+        self.cache.insert(color, index);
+        index
+    }
+
+    // Typical gap between neighbouring entries per channel, how far ordered dithering may push a color.
+    // Worked out once per matcher, it compares every pair of entries.
+    pub fn spread(&self) -> f32 {
+        *self.spread.get_or_init(|| self.measure_spread())
+    }
+
+    fn measure_spread(&self) -> f32 {
+        let gaps: f32 = self.palette.iter()
+            .map(|&a| {
+                let a = [a.0 as f32, a.1 as f32, a.2 as f32];
+
+                self.palette.iter()
+                    .map(|&b| distance_squared(&a, &[b.0 as f32, b.1 as f32, b.2 as f32]))
+                    .filter(|&distance| distance > 0.0)
+                    .fold(f32::MAX, f32::min)
+                    .min(255.0 * 255.0 * 3.0)
+                    .sqrt()
+            })
+            .sum();
+
+        gaps / self.palette.len().max(1) as f32 / 3f32.sqrt()
+    }
+}
+
+const GAME_BOY: [(u8, u8, u8); 4] = [(15, 56, 15), (48, 98, 48), (139, 172, 15), (155, 188, 15)];
+
+const CGA: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xaa), (0x00, 0xaa, 0x00), (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00), (0xaa, 0x00, 0xaa), (0xaa, 0x55, 0x00), (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xff), (0x55, 0xff, 0x55), (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55), (0xff, 0x55, 0xff), (0xff, 0xff, 0x55), (0xff, 0xff, 0xff),
+];
+
+// Base tones from dark to light, then the accents
+const SOLARIZED: [(u8, u8, u8); 16] = [
+    (0x00, 0x2b, 0x36), (0x07, 0x36, 0x42), (0x58, 0x6e, 0x75), (0x65, 0x7b, 0x83),
+    (0x83, 0x94, 0x96), (0x93, 0xa1, 0xa1), (0xee, 0xe8, 0xd5), (0xfd, 0xf6, 0xe3),
+    (0xb5, 0x89, 0x00), (0xcb, 0x4b, 0x16), (0xdc, 0x32, 0x2f), (0xd3, 0x36, 0x82),
+    (0x6c, 0x71, 0xc4), (0x26, 0x8b, 0xd2), (0x2a, 0xa1, 0x98), (0x85, 0x99, 0x00),
+];
+
+/// Colors frames are remapped to, a fixed set or one built from every frame. A fixed palette keeps
+/// its matcher, and with it the matched colors, from frame to frame.
+pub enum Palette {
+    Fixed(PaletteMatcher),
+    Adaptive(PaletteBuilder, Metric),
+}
+
+impl Palette {
+    // Quantizer and built in palette names, anything else has to be an existing palette file
+    pub fn from_name(name: &str, colors: usize, metric: Metric) -> Result<Self, Box<dyn Error>> {
+        if let Some(quantizer) = Quantizer::from_name(name) {
+            return Ok(Palette::Adaptive(PaletteBuilder::new(quantizer, colors), metric));
+        }
+
+        let colors = match name {
+            "gameboy" => GAME_BOY.to_vec(),
+            "cga" => CGA.to_vec(),
+            "solarized" => SOLARIZED.to_vec(),
+            path if Path::new(path).exists() => load(path)?,
+            name => {
+                return Err(format!(
+                    "Unknown palette {}, expected median-cut, octree, kmeans, gameboy, cga, solarized or a palette file",
+                    name,
+                ).into());
+            },
+        };
+
+        Ok(Palette::Fixed(PaletteMatcher::new(colors, metric)))
+    }
+
+    pub fn remap(&mut self, image: &mut Image, dither: Option<Dither>) {
+        match self {
+            Palette::Fixed(matcher) => image.remap(matcher, dither),
+            Palette::Adaptive(builder, metric) => {
+                let mut matcher = PaletteMatcher::new(builder.build(image), *metric);
+
+                image.remap(&mut matcher, dither)
+            },
+        };
+    }
+}
+
+// `#rrggbb`, `rrggbb` or Paint.NET style `aarrggbb`, alpha is ignored
+pub fn parse_hex(token: &str) -> Option<(u8, u8, u8)> {
+    let digits = token.trim_start_matches('#');
+    let digits = match digits.len() {
+        6 => digits,
+        8 => &digits[2..],
+        _ => return None,
+    };
+    let value = u32::from_str_radix(digits, 16).ok()?;
+
+    Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+/// Reads a GIMP palette (.gpl) or a list of hex colors, one or more per line
+pub fn load(path: &str) -> Result<Vec<Rgb>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut colors = Vec::new();
+
+    match text.trim_start().starts_with("GIMP Palette") {
+        true => {
+            for line in text.lines().skip_while(|line| !line.starts_with("GIMP Palette")).skip(1) {
+                let line = line.trim();
+
+                if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+                    continue;
+                }
+
+                let channels: Vec<u8> = line.split_whitespace().take(3).filter_map(|value| value.parse().ok()).collect();
+
+                match channels[..] {
+                    [r, g, b] => colors.push((r, g, b)),
+                    _ => return Err(format!("Invalid palette line {}", line).into()),
+                }
+            }
+        },
+        false => {
+            for line in text.lines() {
+                let line = line.trim();
+
+                // Paint.NET comments
+                if line.is_empty() || line.starts_with(';') || line.starts_with("//") {
+                    continue;
+                }
+
+                for token in line.split(|c: char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()) {
+                    match parse_hex(token) {
+                        Some(color) => colors.push(color),
+                        None => return Err(format!("Invalid hex color {}", token).into()),
+                    }
+                }
+            }
+        },
+    }
+
+    match colors.len() {
+        0 => Err(format!("No colors in {}", path).into()),
+        1..=256 => Ok(colors),
+        _ => Err(format!("{} has more than 256 colors", path).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoothing_survives_fewer_colors() {
+        let previous = [[0.0, 0.0, 0.0], [100.0, 100.0, 100.0], [200.0, 0.0, 0.0], [0.0, 0.0, 200.0]];
+        let fresh = [[10.0, 10.0, 10.0], [210.0, 0.0, 0.0], [0.0, 0.0, 210.0]];
+
+        // The gray entry has no fresh entry left and drifts towards black instead of vanishing
+        assert_eq!(smooth(&previous, &fresh, 0.5), [
+            [5.0, 5.0, 5.0], [55.0, 55.0, 55.0], [205.0, 0.0, 0.0], [0.0, 0.0, 205.0],
+        ]);
+    }
+
+    #[test]
+    fn smoothing_survives_more_colors() {
+        let previous = [[0.0, 0.0, 0.0], [200.0, 0.0, 0.0]];
+        let fresh = [[0.0, 200.0, 0.0], [210.0, 0.0, 0.0], [10.0, 10.0, 10.0]];
+
+        assert_eq!(smooth(&previous, &fresh, 0.5), [[5.0, 5.0, 5.0], [205.0, 0.0, 0.0], [0.0, 200.0, 0.0]]);
+    }
+
+    #[test]
+    fn smoothing_starts_from_the_fresh_palette() {
+        let fresh = [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+
+        assert_eq!(smooth(&[], &fresh, 0.5), fresh);
+    }
+
+    #[test]
+    fn unknown_palette_names_list_the_choices() {
+        let error = Palette::from_name("mediancut", 16, Metric::Oklab).err().unwrap().to_string();
+
+        assert!(error.contains("Unknown palette mediancut"), "{}", error);
+        assert!(error.contains("median-cut"), "{}", error);
+    }
+
+    #[test]
+    fn fixed_palettes_keep_their_matcher() {
+        let path = std::env::temp_dir().join(format!("terminal-render-palette-{}.txt", std::process::id()));
+
+        fs::write(&path, "#000000 ffffff\nff0000\n").unwrap();
+
+        let palette = Palette::from_name(path.to_str().unwrap(), 16, Metric::Rgb);
+
+        fs::remove_file(&path).unwrap();
+
+        match palette.unwrap() {
+            Palette::Fixed(mut matcher) => {
+                let index = matcher.nearest((250, 10, 10));
+
+                assert_eq!(matcher.color(index), (255, 0, 0));
+            },
+            Palette::Adaptive(..) => panic!("a palette file is fixed"),
+        }
+    }
+}
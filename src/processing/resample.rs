@@ -0,0 +1,249 @@
+use std::f64::consts::PI;
+
+use crossterm::style::Color;
+use crate::{color::BlendSpace, frame::Size, pixel::{PxData, Vector2}, pixel};
+
+use super::Scaling;
+
+// Catmull-Rom, the cubic with a = -0.5
+fn cubic(x: f64) -> f64 {
+    let x = x.abs();
+
+    match x {
+        _ if x < 1.0 => 1.5 * x * x * x - 2.5 * x * x + 1.0,
+        _ if x < 2.0 => -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0,
+        _ => 0.0,
+    }
+}
+
+// Tent, what bilinear interpolation weighs its two neighbours with
+fn triangle(x: f64) -> f64 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+fn sinc(x: f64) -> f64 {
+    match x == 0.0 {
+        true => 1.0,
+        false => (PI * x).sin() / (PI * x),
+    }
+}
+
+fn lanczos(x: f64, window: f64) -> f64 {
+    match x.abs() < window {
+        true => sinc(x) * sinc(x / window),
+        false => 0.0,
+    }
+}
+
+// First source index and normalized weights for every destination index along one axis
+fn weights(source_length: u32, length: u32, support: f64, kernel: &dyn Fn(f64) -> f64) -> Vec<(usize, Vec<f64>)> {
+    let scale = source_length as f64 / length as f64;
+    // Widen the kernel when shrinking so every source pixel still contributes
+    let filter_scale = scale.max(1.0);
+    let radius = support * filter_scale;
+    let mut result = Vec::with_capacity(length as usize);
+
+    for index in 0..length {
+        let center = (index as f64 + 0.5) * scale;
+        let start = (center - radius).floor().max(0.0) as usize;
+        let end = ((center + radius).ceil() as usize).min(source_length as usize);
+        let mut row: Vec<f64> = (start..end)
+            .map(|source_index| kernel((source_index as f64 + 0.5 - center) / filter_scale))
+            .collect();
+        let sum: f64 = row.iter().sum();
+
+        if sum != 0.0 {
+            row.iter_mut().for_each(|weight| *weight /= sum);
+        }
+
+        result.push((start, row));
+    }
+
+    result
+}
+
+// The single source pixel every destination pixel copies along one axis
+fn nearest(source_length: u32, length: u32) -> Vec<(usize, Vec<f64>)> {
+    (0..length)
+        .map(|index| ((index as u64 * source_length as u64 / length as u64) as usize, vec![1.0]))
+        .collect()
+}
+
+// Share of every destination pixel's footprint each source pixel covers along one axis
+fn coverage(source_length: u32, length: u32) -> Vec<(usize, Vec<f64>)> {
+    let scale = source_length as f64 / length as f64;
+    let mut result = Vec::with_capacity(length as usize);
+
+    for index in 0..length {
+        let low = index as f64 * scale;
+        let high = ((index + 1) as f64 * scale).min(source_length as f64);
+        let start = low.floor() as usize;
+        let end = (high.ceil() as usize).max(start + 1);
+        let row = (start..end)
+            .map(|source_index| (high.min(source_index as f64 + 1.0) - low.max(source_index as f64)) / (high - low))
+            .collect();
+
+        result.push((start, row));
+    }
+
+    result
+}
+
+/// Separable resize for every scaling type. Bilinear uses a tent widened on reduction here,
+/// unlike the four sample version `Image::scale` uses for it.
+pub fn resample(pixel_data: &[PxData], original_size: Size, size: Size, scaling: &Scaling, space: BlendSpace) -> Vec<PxData> {
+    let (horizontal, vertical) = match scaling {
+        Scaling::Nearest => (nearest(original_size.width, size.width), nearest(original_size.height, size.height)),
+        Scaling::Bilinear => (
+            weights(original_size.width, size.width, 1.0, &triangle),
+            weights(original_size.height, size.height, 1.0, &triangle),
+        ),
+        Scaling::Bicubic => (
+            weights(original_size.width, size.width, 2.0, &cubic),
+            weights(original_size.height, size.height, 2.0, &cubic),
+        ),
+        Scaling::Lanczos(window) => {
+            let window = (*window).max(1) as f64;
+            let kernel = |x| lanczos(x, window);
+
+            (
+                weights(original_size.width, size.width, window, &kernel),
+                weights(original_size.height, size.height, window, &kernel),
+            )
+        },
+        Scaling::Area => (coverage(original_size.width, size.width), coverage(original_size.height, size.height)),
+    };
+
+    convolve(pixel_data, original_size, size, &horizontal, &vertical, space)
+}
+
+fn convolve(
+    pixel_data: &[PxData],
+    original_size: Size,
+    size: Size,
+    horizontal: &[(usize, Vec<f64>)],
+    vertical: &[(usize, Vec<f64>)],
+    space: BlendSpace,
+) -> Vec<PxData> {
+    let (source_width, source_height) = (original_size.width as usize, original_size.height as usize);
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut rows = vec![[0.0f64; 3]; source_height * width];
+
+    for y in 0..source_height {
+        for (x, (start, row)) in horizontal.iter().enumerate() {
+            let mut sum = [0.0; 3];
+
+            for (offset, weight) in row.iter().enumerate() {
+                let (r, g, b) = pixel_data[y * source_width + start + offset].get_color_raw();
+
+                sum[0] += space.decode(r) as f64 * weight;
+                sum[1] += space.decode(g) as f64 * weight;
+                sum[2] += space.decode(b) as f64 * weight;
+            }
+
+            rows[y * width + x] = sum;
+        }
+    }
+
+    let mut resized_pixel_data = Vec::with_capacity(width * height);
+
+    for (y, (start, column)) in vertical.iter().enumerate() {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+
+            for (offset, weight) in column.iter().enumerate() {
+                let value = rows[(start + offset) * width + x];
+
+                sum[0] += value[0] * weight;
+                sum[1] += value[1] * weight;
+                sum[2] += value[2] * weight;
+            }
+
+            // Negative lobes overshoot around edges, encoding clamps them back
+            let channel = |value: f64| space.encode(value as f32);
+
+            resized_pixel_data.push(pixel!(channel(sum[0]), channel(sum[1]), channel(sum[2]), x as u32, y as u32));
+        }
+    }
+
+    resized_pixel_data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source::pattern::Pattern;
+    use super::*;
+
+    // Reference values come from a straightforward float implementation of the same kernels,
+    // sampled at pixel centers and rounded once at the end
+    const KERNELS: [Scaling; 3] = [Scaling::Bicubic, Scaling::Lanczos(2), Scaling::Lanczos(3)];
+
+    fn scale(pattern: Pattern, from: (u32, u32), to: (u32, u32), scaling: &Scaling) -> Vec<u8> {
+        let original_size = Size { width: from.0, height: from.1 };
+        let mut pixel_data = Vec::new();
+
+        for y in 0..from.1 {
+            for x in 0..from.0 {
+                let (r, g, b) = pattern.pixel(x, y, original_size, 0);
+
+                pixel_data.push(pixel!(r, g, b, x, y));
+            }
+        }
+
+        resample(&pixel_data, original_size, Size { width: to.0, height: to.1 }, scaling, BlendSpace::Srgb).iter()
+            .map(|pixel| pixel.get_r())
+            .collect()
+    }
+
+    #[test]
+    fn ramp_upscale() {
+        let expected: [[u8; 16]; 3] = [
+            [0, 2, 18, 40, 61, 80, 99, 118, 137, 156, 175, 194, 215, 237, 253, 255],
+            [0, 2, 18, 39, 62, 79, 98, 119, 136, 157, 176, 193, 216, 237, 253, 255],
+            [0, 1, 16, 39, 63, 83, 99, 117, 138, 156, 172, 192, 216, 239, 254, 255],
+        ];
+
+        for (scaling, expected) in KERNELS.iter().zip(expected) {
+            assert_eq!(scale(Pattern::Gradient, (6, 1), (16, 1), scaling), expected);
+        }
+    }
+
+    #[test]
+    fn checkerboard_downscale() {
+        let expected: [[u8; 3]; 3] = [[255, 73, 148], [255, 72, 148], [255, 59, 154]];
+
+        for (scaling, [corner, edge, middle]) in KERNELS.iter().zip(expected) {
+            let output = scale(Pattern::Checkerboard { cell: 3 }, (9, 9), (4, 4), scaling);
+
+            assert_eq!(output, [
+                corner, edge, edge, corner,
+                edge, middle, middle, edge,
+                edge, middle, middle, edge,
+                corner, edge, edge, corner,
+            ]);
+        }
+    }
+
+    #[test]
+    fn zone_plate_downscale() {
+        let expected: [[u8; 8]; 3] = [
+            [140, 147, 59, 229, 255, 137, 92, 117],
+            [140, 146, 58, 230, 255, 137, 93, 119],
+            [146, 139, 55, 242, 255, 135, 89, 126],
+        ];
+
+        for (scaling, expected) in KERNELS.iter().zip(expected) {
+            // Center row, the zone plate is rotationally symmetric around (8, 8)
+            assert_eq!(scale(Pattern::ZonePlate, (16, 16), (8, 8), scaling)[32..40], expected);
+        }
+    }
+
+    #[test]
+    fn flat_color_stays_flat() {
+        let flat = Pattern::Checkerboard { cell: 64 };
+
+        for scaling in [Scaling::Nearest, Scaling::Bilinear, Scaling::Area].iter().chain(KERNELS.iter()) {
+            assert!(scale(flat, (12, 10), (7, 13), scaling).iter().all(|&value| value == 255));
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::io::Write;
+
+use crate::processing::dither::{self, Dither};
+use crate::processing::palette::{Metric, PaletteMatcher};
+use crate::term::ColorDepth;
+use super::xterm;
+use super::Cell;
+
+// Color as the terminal gets it, either direct RGB or a palette index
+#[derive(Clone, Copy, PartialEq)]
+enum TermColor {
+    Rgb(u8, u8, u8),
+    Indexed(u8),
+}
+
+// Above this share of changed cells a full repaint is cheaper than cursor jumps
+const FULL_REPAINT_RATIO: f32 = 0.5;
+
+// Unchanged gaps up to this many cells are rewritten rather than jumped over
+const MAX_GAP: usize = 3;
+
+// A cell after palette matching, what actually ends up on screen
+#[derive(Clone, Copy, PartialEq)]
+struct ScreenCell {
+    character: char,
+    fg: Option<TermColor>,
+    bg: Option<TermColor>,
+}
+
+// Colors the terminal is currently drawing with, `None` until an SGR has set them this frame
+#[derive(Clone, Copy, Default)]
+struct SgrState {
+    fg: Option<TermColor>,
+    bg: Option<Option<TermColor>>,
+}
+
+/// Writes cells as SGR sequences for the terminal's color depth, rows wrap at the terminal edge.
+/// Only the runs of cells that changed since the last frame are repainted, and colors are only
+/// sent when they differ from what the terminal is already drawing with.
+pub struct AnsiEncoder {
+    depth: ColorDepth,
+    matcher: Option<PaletteMatcher>,
+    dither: Option<Dither>,
+    tolerance: u8,
+    state: SgrState,
+    last_frame: Vec<ScreenCell>,
+    last_columns: usize,
+}
+
+impl AnsiEncoder {
+    pub fn new(depth: ColorDepth) -> Self {
+        let matcher = match depth {
+            ColorDepth::Ansi256 => Some(PaletteMatcher::new(xterm::palette_256(), Metric::Oklab)),
+            ColorDepth::Ansi16 => Some(PaletteMatcher::new(xterm::ANSI_16.to_vec(), Metric::Oklab)),
+            _ => None,
+        };
+
+        Self {
+            depth,
+            matcher,
+            dither: None,
+            tolerance: 0,
+            state: SgrState::default(),
+            last_frame: Vec::new(),
+            last_columns: 0,
+        }
+    }
+
+    // Forget what is on screen, the next frame is a full repaint
+    pub fn invalidate(&mut self) {
+        self.last_frame.clear();
+    }
+
+    // Spread the palette matching error over neighbouring cells, only matters below truecolor
+    pub fn set_dither(&mut self, dither: Option<Dither>) {
+        self.dither = dither;
+    }
+
+    // Renderers that quantize on their own, like the ASCII ramp, follow the same setting
+    pub fn get_dither(&self) -> Option<Dither> {
+        self.dither
+    }
+
+    // Truecolor channels within `tolerance` of the current color reuse it instead of sending a new SGR
+    pub fn set_tolerance(&mut self, tolerance: u8) {
+        self.tolerance = tolerance;
+    }
+
+    fn is_close(&self, a: Option<TermColor>, b: Option<TermColor>) -> bool {
+        match (a, b) {
+            (Some(TermColor::Rgb(r1, g1, b1)), Some(TermColor::Rgb(r2, g2, b2))) => {
+                r1.abs_diff(r2) <= self.tolerance && g1.abs_diff(g2) <= self.tolerance && b1.abs_diff(b2) <= self.tolerance
+            },
+            _ => a == b,
+        }
+    }
+
+    // Spaces only show their background, so their foreground never matters
+    fn looks_same(&self, a: &ScreenCell, b: &ScreenCell) -> bool {
+        a.character == b.character
+            && self.is_close(a.bg, b.bg)
+            && (a.character == ' ' || self.is_close(a.fg, b.fg))
+    }
+
+    fn map_layer(&mut self, colors: Vec<Option<(u8, u8, u8)>>, columns: usize) -> Vec<Option<TermColor>> {
+        // Roughly the gap between neighbouring palette colors, how far ordered dithering may push a color
+        let spread = match self.depth {
+            ColorDepth::Ansi256 => 51.0,
+            _ => 85.0,
+        };
+
+        match &mut self.matcher {
+            Some(matcher) => match_layer(matcher, &colors, columns, self.dither, spread),
+            None => colors.into_iter()
+                .map(|color| color.map(|(r, g, b)| TermColor::Rgb(r, g, b)))
+                .collect(),
+        }
+    }
+
+    fn write_color(&self, output: &mut Vec<u8>, color: TermColor, background: bool) {
+        match (color, background) {
+            (TermColor::Rgb(r, g, b), false) => write!(output, "38;2;{};{};{}", r, g, b),
+            (TermColor::Rgb(r, g, b), true) => write!(output, "48;2;{};{};{}", r, g, b),
+            (TermColor::Indexed(index), background) => match (self.depth, index < 8) {
+                (ColorDepth::Ansi256, _) => {
+                    write!(output, "{};5;{}", if background { 48 } else { 38 }, index + xterm::PALETTE_256_START)
+                },
+                (_, true) => write!(output, "{}", if background { 40 } else { 30 } + index as u32),
+                (_, false) => write!(output, "{}", if background { 100 } else { 90 } + index as u32 - 8),
+            },
+        }.unwrap();
+    }
+
+    // Writes the cell with as little SGR as the current state allows, returns what ended up on screen
+    fn write_cell(&mut self, output: &mut Vec<u8>, cell: &ScreenCell) -> ScreenCell {
+        let mut drawn = *cell;
+
+        // Monochrome gets characters only, the shape has to carry the image
+        if self.depth != ColorDepth::Monochrome {
+            let fg_changed = cell.character != ' ' && cell.fg.is_some() && !self.is_close(self.state.fg, cell.fg);
+            let bg_changed = match self.state.bg {
+                Some(bg) => !self.is_close(bg, cell.bg),
+                None => true,
+            };
+
+            if fg_changed || bg_changed {
+                output.extend_from_slice(b"\x1b[");
+
+                if fg_changed {
+                    self.write_color(output, cell.fg.unwrap(), false);
+                    self.state.fg = cell.fg;
+                }
+
+                if bg_changed {
+                    if fg_changed {
+                        output.push(b';');
+                    }
+
+                    match cell.bg {
+                        Some(bg) => self.write_color(output, bg, true),
+                        None => output.extend_from_slice(b"49"),
+                    }
+
+                    self.state.bg = Some(cell.bg);
+                }
+
+                output.push(b'm');
+            }
+
+            if cell.character != ' ' {
+                drawn.fg = self.state.fg;
+            }
+
+            drawn.bg = self.state.bg.unwrap_or(cell.bg);
+        }
+
+        let mut utf8 = [0; 4];
+        output.extend_from_slice(cell.character.encode_utf8(&mut utf8).as_bytes());
+        drawn
+    }
+
+    pub fn encode(&mut self, cells: &[Cell], columns: u32, output: &mut Vec<u8>) {
+        let columns = columns.max(1) as usize;
+        let fg = self.map_layer(cells.iter().map(|cell| Some(cell.fg)).collect(), columns);
+        let bg = self.map_layer(cells.iter().map(|cell| cell.bg).collect(), columns);
+        let frame: Vec<ScreenCell> = cells.iter().enumerate()
+            .map(|(index, cell)| ScreenCell { character: cell.character, fg: fg[index], bg: bg[index] })
+            .collect();
+        let resized = frame.len() != self.last_frame.len() || columns != self.last_columns;
+        let changed = match resized {
+            true => frame.len(),
+            false => frame.iter().zip(self.last_frame.iter()).filter(|(cell, last)| !self.looks_same(cell, last)).count(),
+        };
+
+        // Whatever else was written since the last frame may have changed the colors
+        self.state = SgrState::default();
+
+        if resized || changed as f32 > frame.len() as f32 * FULL_REPAINT_RATIO {
+            self.last_frame = frame.iter().map(|cell| self.write_cell(output, cell)).collect();
+        } else {
+            let mut index = 0;
+
+            while index < frame.len() {
+                if self.looks_same(&frame[index], &self.last_frame[index]) {
+                    index += 1;
+                    continue;
+                }
+
+                // Jump to the start of the changed run, rows wrap on their own from here
+                write!(output, "\x1b[{};{}H", index / columns + 1, index % columns + 1).unwrap();
+
+                while index < frame.len() {
+                    let gap = (index..frame.len())
+                        .take_while(|&gap_index| self.looks_same(&frame[gap_index], &self.last_frame[gap_index]))
+                        .take(MAX_GAP + 1)
+                        .count();
+
+                    if gap > MAX_GAP || index + gap == frame.len() {
+                        index += gap;
+                        break;
+                    }
+
+                    for (offset, cell) in frame[index..index + gap + 1].iter().enumerate() {
+                        self.last_frame[index + offset] = self.write_cell(output, cell);
+                    }
+
+                    index += gap + 1;
+                }
+            }
+        }
+
+        self.last_columns = columns;
+    }
+}
+
+// Nearest palette entries for one color layer of the cell grid, optionally dithered
+fn match_layer(
+    matcher: &mut PaletteMatcher,
+    colors: &[Option<(u8, u8, u8)>],
+    columns: usize,
+    dither: Option<Dither>,
+    spread: f32,
+) -> Vec<Option<TermColor>> {
+    let diffusion = match dither {
+        Some(Dither::Diffusion(diffusion)) => diffusion,
+        Some(Dither::Ordered(matrix)) => {
+            return colors.iter().enumerate()
+                .map(|(index, color)| color.map(|(r, g, b)| {
+                    let offset = (matrix.threshold((index % columns) as u32, (index / columns) as u32) - 0.5) * spread;
+                    let channel = |value: u8| (value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+
+                    TermColor::Indexed(matcher.nearest((channel(r), channel(g), channel(b))))
+                }))
+                .collect();
+        },
+        None => {
+            return colors.iter()
+                .map(|color| color.map(|color| TermColor::Indexed(matcher.nearest(color))))
+                .collect();
+        }
+    };
+    let wanted: Vec<Option<[f32; 3]>> = colors.iter()
+        .map(|color| color.map(|(r, g, b)| [r as f32, g as f32, b as f32]))
+        .collect();
+    let mut matched = vec![None; colors.len()];
+
+    dither::diffuse(&wanted, columns, diffusion, |index, wanted| {
+        let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+        let palette_index = matcher.nearest((channel(wanted[0]), channel(wanted[1]), channel(wanted[2])));
+        let actual = matcher.color(palette_index);
+
+        matched[index] = Some(TermColor::Indexed(palette_index));
+        [actual.0 as f32, actual.1 as f32, actual.2 as f32]
+    });
+
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+    use crate::frame::{Image, Size};
+    use crate::pixel::{PxData, Vector2};
+    use crate::pixel;
+    use crate::render::{block::Block, Renderer};
+    use crate::source::pattern::Pattern;
+    use super::*;
+
+    // Half block cells of an 80x24 terminal showing the pattern
+    fn pattern_cells(pattern: Pattern) -> Vec<Cell> {
+        let size = Size { width: 80, height: 48 };
+        let mut cells = Vec::new();
+
+        for y in 0..24 {
+            for x in 0..80 {
+                cells.push(Cell {
+                    character: '▀',
+                    fg: pattern.pixel(x, y * 2, size, 0),
+                    bg: Some(pattern.pixel(x, y * 2 + 1, size, 0)),
+                });
+            }
+        }
+
+        cells
+    }
+
+    fn image(width: u32, height: u32, color: impl Fn(u32, u32) -> (u8, u8, u8)) -> Image {
+        let mut pixel_data = Vec::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = color(x, y);
+
+                pixel_data.push(pixel!(r, g, b, x, y));
+            }
+        }
+
+        Image::new(pixel_data, Size { width, height }, 3)
+    }
+
+    // What the canvas wrote before the encoder, a foreground SGR in front of every pixel that isn't almost black
+    fn per_cell_output(image: &Image) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        for pixel in image.get_pixel_data() {
+            let (r, g, b) = pixel.get_color_raw();
+
+            match r < 10 && g < 10 && b < 10 {
+                true => output.push(b' '),
+                false => write!(output, "\x1b[38;2;{};{};{}m{}", r, g, b, pixel.get_character()).unwrap(),
+            }
+        }
+
+        output
+    }
+
+    fn block_output(image: &Image) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        Block::new(AnsiEncoder::new(ColorDepth::TrueColor)).render(image, image.get_image_size(), &mut output);
+        output
+    }
+
+    fn encode_colors(depth: ColorDepth, colors: &[(u8, u8, u8)]) -> String {
+        let mut encoder = AnsiEncoder::new(depth);
+        let cells: Vec<Cell> = colors.iter()
+            .map(|&fg| Cell { character: '#', fg, bg: None })
+            .collect();
+        let mut output = Vec::new();
+
+        encoder.encode(&cells, cells.len() as u32, &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn ansi_256_skips_the_themed_colors() {
+        let output = encode_colors(ColorDepth::Ansi256, &[(255, 0, 0), (0, 0, 0), (128, 128, 128), (255, 255, 255)]);
+
+        // Cube red, cube black, a gray ramp step and cube white, never one of the first 16 indices
+        assert_eq!(output, "\x1b[38;5;196;49m#\x1b[38;5;16m#\x1b[38;5;244m#\x1b[38;5;231m#");
+    }
+
+    #[test]
+    fn ansi_16_uses_the_named_colors() {
+        let output = encode_colors(ColorDepth::Ansi16, &[(250, 10, 10), (0, 0, 0)]);
+
+        assert_eq!(output, "\x1b[91;49m#\x1b[30m#");
+    }
+
+    #[test]
+    fn coalesced_frames_are_smaller() {
+        let size = Size { width: 80, height: 24 };
+
+        for pattern in [Pattern::ColorBars, Pattern::TextGrid { cell: 32 }, Pattern::Checkerboard { cell: 8 }] {
+            let image = image(80, 24, |x, y| pattern.pixel(x, y, size, 0));
+            let (coalesced, per_cell) = (block_output(&image).len(), per_cell_output(&image).len());
+
+            assert!(coalesced * 2 < per_cell, "{} bytes against {}", coalesced, per_cell);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a photo, set TERMINAL_RENDER_SAMPLE to its path"]
+    fn natural_image_byte_counts() {
+        let path = std::env::var("TERMINAL_RENDER_SAMPLE").unwrap();
+        let photo = image::open(path).unwrap().resize_exact(160, 48, image::imageops::FilterType::Triangle).to_rgb8();
+        let image = image(160, 48, |x, y| photo.get_pixel(x, y).0.into());
+
+        println!("per cell {} bytes, coalesced {} bytes", per_cell_output(&image).len(), block_output(&image).len());
+    }
+
+    #[test]
+    fn changed_runs_are_jumped_to() {
+        let white = vec![Cell { character: '#', fg: (255, 255, 255), bg: None }; 30];
+        let repaint = |changed: &[usize]| {
+            let mut encoder = AnsiEncoder::new(ColorDepth::TrueColor);
+            let mut cells = white.clone();
+            let mut output = Vec::new();
+
+            encoder.encode(&white, 10, &mut output);
+
+            for &index in changed {
+                cells[index].fg = (255, 0, 0);
+            }
+
+            output.clear();
+            encoder.encode(&cells, 10, &mut output);
+            String::from_utf8(output).unwrap()
+        };
+
+        // One unchanged cell between the two is rewritten instead of jumped over
+        assert_eq!(repaint(&[12, 14]), "\x1b[2;3H\x1b[38;2;255;0;0;49m#\x1b[38;2;255;255;255m#\x1b[38;2;255;0;0m#");
+        // A longer gap gets a second jump
+        assert_eq!(repaint(&[12, 25]), "\x1b[2;3H\x1b[38;2;255;0;0;49m#\x1b[3;6H#");
+    }
+
+    #[test]
+    fn unchanged_frames_write_nothing() {
+        let cells = pattern_cells(Pattern::ColorBars);
+        let mut encoder = AnsiEncoder::new(ColorDepth::TrueColor);
+        let mut output = Vec::new();
+
+        encoder.encode(&cells, 80, &mut output);
+        output.clear();
+        encoder.encode(&cells, 80, &mut output);
+
+        assert!(output.is_empty());
+    }
+}
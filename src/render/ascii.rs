@@ -0,0 +1,69 @@
+use crate::color;
+use crate::frame::{Image, Size};
+use crate::processing::dither::{self, Dither};
+use super::{ansi::AnsiEncoder, Cell, Renderer};
+
+/// One pixel per cell picked from a brightness ramp, readable even without any color
+pub struct Ascii {
+    shades: Vec<char>,
+    encoder: AnsiEncoder,
+}
+
+impl Ascii {
+    // `shades` goes from darkest to brightest
+    pub fn new(shades: &str, encoder: AnsiEncoder) -> Self {
+        Self {
+            shades: shades.chars().collect(),
+            encoder,
+        }
+    }
+}
+
+impl Renderer for Ascii {
+    fn cell_pixels(&self) -> Size {
+        Size { width: 1, height: 1 }
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let shades_len = self.shades.len() as f64;
+        let mut cells: Vec<Cell> = image.get_pixel_data().iter()
+            .map(|pixel| {
+                let (r, g, b) = pixel.get_color_raw();
+                let average = color::luminance((r, g, b)) as f64;
+                let shade_index = (average / 255.0 * shades_len).floor() as usize;
+                let character = self.shades[shade_index.min(self.shades.len() - 1)];
+
+                Cell { character, fg: (r, g, b), bg: None }
+            })
+            .collect();
+
+        // Dithered, each character stands for an evenly spaced brightness
+        let steps = (self.shades.len() - 1).max(1) as f32;
+        let width = image.get_image_size().width.max(1) as usize;
+        let brightness: Vec<Option<[f32; 3]>> = cells.iter()
+            .map(|cell| Some([color::luminance(cell.fg) as f32; 3]))
+            .collect();
+
+        match self.encoder.get_dither() {
+            Some(Dither::Diffusion(diffusion)) => {
+                dither::diffuse(&brightness, width, diffusion, |index, wanted| {
+                    let shade_index = (wanted[0] / 255.0 * steps).round().clamp(0.0, steps) as usize;
+
+                    cells[index].character = self.shades[shade_index];
+                    [shade_index as f32 * 255.0 / steps; 3]
+                });
+            },
+            Some(Dither::Ordered(matrix)) => {
+                for (index, cell) in cells.iter_mut().enumerate() {
+                    let threshold = matrix.threshold((index % width) as u32, (index / width) as u32);
+                    let wanted = brightness[index].unwrap()[0] / 255.0 * steps;
+
+                    cell.character = self.shades[(wanted + threshold - 0.5).round().clamp(0.0, steps) as usize];
+                }
+            },
+            None => {},
+        }
+
+        self.encoder.encode(&cells, term_size.width, output);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::frame::{Image, Size};
+use super::{ansi::AnsiEncoder, Cell, Renderer};
+
+/// One pixel per cell drawn with the pixel's own character, `'█'` unless a filter set another
+pub struct Block {
+    encoder: AnsiEncoder,
+}
+
+impl Block {
+    pub fn new(encoder: AnsiEncoder) -> Self {
+        Self {
+            encoder,
+        }
+    }
+}
+
+impl Renderer for Block {
+    fn cell_pixels(&self) -> Size {
+        Size { width: 1, height: 1 }
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let cells: Vec<Cell> = image.get_pixel_data().iter()
+            .map(|pixel| {
+                let (r, g, b) = pixel.get_color_raw();
+
+                // If almost black no render
+                let character = match r < 10 && g < 10 && b < 10 {
+                    true => ' ',
+                    false => pixel.get_character(),
+                };
+
+                Cell { character, fg: (r, g, b), bg: None }
+            })
+            .collect();
+
+        self.encoder.encode(&cells, term_size.width, output);
+    }
+}
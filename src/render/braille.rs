@@ -0,0 +1,137 @@
+use crate::color;
+use crate::frame::{Image, Size};
+use crate::pixel::Vector2;
+use crate::processing::dither::Matrix;
+use crate::vector2;
+use super::{ansi::AnsiEncoder, Cell, Renderer};
+
+// Dot bit for each pixel of the 2x4 block, indexed [y][x]
+const DOTS: [[u32; 2]; 4] = [
+    [0x01, 0x08],
+    [0x02, 0x10],
+    [0x04, 0x20],
+    [0x40, 0x80],
+];
+
+#[derive(Clone, Copy)]
+pub enum Activation {
+    // Dot is lit when the pixel brightness is above the value
+    Threshold(u8),
+    // Ordered dithering, dots follow the screen so still areas don't shimmer
+    Dither(Matrix),
+}
+
+/// Each 2x4 block of pixels becomes one Braille glyph (U+2800 - U+28FF)
+pub struct Braille {
+    activation: Activation,
+    monochrome: bool,
+    encoder: AnsiEncoder,
+}
+
+impl Braille {
+    pub fn new(activation: Activation, monochrome: bool, encoder: AnsiEncoder) -> Self {
+        Self {
+            activation,
+            monochrome,
+            encoder,
+        }
+    }
+
+    fn is_lit(&self, brightness: u8, x: u32, y: u32) -> bool {
+        match self.activation {
+            Activation::Threshold(threshold) => brightness > threshold,
+            Activation::Dither(matrix) => brightness as f32 > matrix.threshold(x, y) * 255.0,
+        }
+    }
+}
+
+impl Renderer for Braille {
+    fn cell_pixels(&self) -> Size {
+        Size { width: 2, height: 4 }
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let mut cells = Vec::with_capacity((term_size.width * term_size.height) as usize);
+
+        for row in 0..term_size.height {
+            for column in 0..term_size.width {
+                let mut pattern = 0;
+                let (mut r, mut g, mut b, mut lit) = (0u32, 0u32, 0u32, 0u32);
+
+                for dy in 0..4 {
+                    for dx in 0..2 {
+                        let (x, y) = (column * 2 + dx, row * 4 + dy);
+                        let (pixel_r, pixel_g, pixel_b) = image.get_pixel(vector2!(x, y)).get_color_raw();
+                        let brightness = color::luminance((pixel_r, pixel_g, pixel_b));
+
+                        // Only the lit dots show the color, dark ones would dim it
+                        if self.is_lit(brightness, x, y) {
+                            pattern |= DOTS[dy as usize][dx as usize];
+                            r += pixel_r as u32;
+                            g += pixel_g as u32;
+                            b += pixel_b as u32;
+                            lit += 1;
+                        }
+                    }
+                }
+
+                let lit = lit.max(1);
+                let fg = match self.monochrome {
+                    true => (255, 255, 255),
+                    false => ((r / lit) as u8, (g / lit) as u8, (b / lit) as u8),
+                };
+                let character = char::from_u32(0x2800 + pattern).unwrap_or(' ');
+
+                cells.push(Cell { character, fg, bg: None });
+            }
+        }
+
+        self.encoder.encode(&cells, term_size.width, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+    use crate::pixel::PxData;
+    use crate::pixel;
+    use crate::term::ColorDepth;
+    use super::*;
+
+    fn render(lit: &[(u32, u32, (u8, u8, u8))], threshold: u8) -> String {
+        let mut pixel_data = Vec::new();
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let (r, g, b) = lit.iter()
+                    .find(|&&(lit_x, lit_y, _)| (lit_x, lit_y) == (x, y))
+                    .map_or((0, 0, 0), |&(_, _, color)| color);
+
+                pixel_data.push(pixel!(r, g, b, x, y));
+            }
+        }
+
+        let image = Image::new(pixel_data, Size { width: 4, height: 4 }, 3);
+        let mut renderer = Braille::new(Activation::Threshold(threshold), false, AnsiEncoder::new(ColorDepth::TrueColor));
+        let mut output = Vec::new();
+
+        renderer.render(&image, Size { width: 2, height: 1 }, &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn dots_follow_the_braille_layout() {
+        // Bottom right dot of the first cell, then the top left and third row right dots of the second
+        let output = render(&[(1, 3, (255, 0, 0)), (2, 0, (0, 0, 255)), (3, 2, (0, 0, 255))], 10);
+
+        assert_eq!(output, "\x1b[38;2;255;0;0;49m\u{2880}\x1b[38;2;0;0;255m\u{2821}");
+    }
+
+    #[test]
+    fn only_lit_dots_set_the_color() {
+        // One dot under the threshold, it stays dark and doesn't pull the color down
+        let output = render(&[(0, 0, (200, 200, 200)), (1, 0, (100, 100, 100)), (0, 1, (20, 20, 20))], 64);
+
+        assert!(output.starts_with("\x1b[38;2;150;150;150;49m\u{2809}"), "{:?}", output);
+    }
+}
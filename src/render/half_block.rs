@@ -0,0 +1,66 @@
+use crate::frame::{Image, Size};
+use crate::pixel::Vector2;
+use crate::vector2;
+use super::{ansi::AnsiEncoder, Cell, Renderer};
+
+/// Two vertically stacked pixels per cell, top one as the `'▀'` foreground and bottom one as the background
+pub struct HalfBlock {
+    encoder: AnsiEncoder,
+}
+
+impl HalfBlock {
+    pub fn new(encoder: AnsiEncoder) -> Self {
+        Self {
+            encoder,
+        }
+    }
+}
+
+impl Renderer for HalfBlock {
+    fn cell_pixels(&self) -> Size {
+        Size { width: 1, height: 2 }
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let mut cells = Vec::with_capacity((term_size.width * term_size.height) as usize);
+
+        for row in 0..term_size.height {
+            for x in 0..term_size.width {
+                let top = image.get_pixel(vector2!(x, row * 2)).get_color_raw();
+                let bottom = image.get_pixel(vector2!(x, row * 2 + 1)).get_color_raw();
+
+                cells.push(Cell { character: '▀', fg: top, bg: Some(bottom) });
+            }
+        }
+
+        self.encoder.encode(&cells, term_size.width, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+    use crate::pixel::PxData;
+    use crate::pixel;
+    use crate::term::ColorDepth;
+    use super::*;
+
+    #[test]
+    fn top_pixel_is_the_foreground() {
+        // Red over blue, then green over white
+        let pixel_data = vec![
+            pixel!(255, 0, 0, 0, 0), pixel!(0, 255, 0, 1, 0),
+            pixel!(0, 0, 255, 0, 1), pixel!(255, 255, 255, 1, 1),
+        ];
+        let image = Image::new(pixel_data, Size { width: 2, height: 2 }, 3);
+        let mut renderer = HalfBlock::new(AnsiEncoder::new(ColorDepth::TrueColor));
+        let mut output = Vec::new();
+
+        renderer.render(&image, Size { width: 2, height: 1 }, &mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\x1b[38;2;255;0;0;48;2;0;0;255m▀\x1b[38;2;0;255;0;48;2;255;255;255m▀",
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::Write;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ExtendedColorType, ImageEncoder};
+
+use crate::frame::{Image, Size};
+use super::{rgb_bytes, Renderer};
+
+/// iTerm2 inline images (OSC 1337 File=), also understood by WezTerm and others, each frame sent as a PNG
+pub struct ITerm {
+    cell_pixels: Size,
+}
+
+impl ITerm {
+    pub fn new(cell_pixels: Size) -> Self {
+        Self { cell_pixels }
+    }
+}
+
+impl Renderer for ITerm {
+    fn cell_pixels(&self) -> Size {
+        self.cell_pixels
+    }
+
+    fn square_pixels(&self) -> bool {
+        true
+    }
+
+    // Keep the last row free, the cursor ends up below the image and would scroll the terminal
+    fn reserved_rows(&self) -> u32 {
+        1
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let Size { width, height } = image.get_image_size();
+        let rgb = rgb_bytes(image, height);
+        let mut png = Vec::new();
+        let encoder = PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::Sub);
+
+        if encoder.write_image(&rgb, width, height, ExtendedColorType::Rgb8).is_err() {
+            return;
+        }
+
+        write!(
+            output,
+            "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0:",
+            png.len(), term_size.width, term_size.height,
+        ).unwrap();
+        output.extend_from_slice(STANDARD.encode(&png).as_bytes());
+        output.push(0x07);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+    use crate::pixel::{PxData, Vector2};
+    use crate::pixel;
+    use super::*;
+
+    #[test]
+    fn sends_the_frame_as_a_png() {
+        let image = Image::new(vec![pixel!(255, 0, 0, 0, 0), pixel!(0, 0, 255, 1, 0)], Size { width: 2, height: 1 }, 3);
+        let mut output = Vec::new();
+
+        ITerm::new(Size { width: 2, height: 1 }).render(&image, Size { width: 1, height: 1 }, &mut output);
+
+        let output = String::from_utf8(output).unwrap();
+        let (header, payload) = output.split_once(':').unwrap();
+        let png = STANDARD.decode(payload.strip_suffix('\x07').unwrap()).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgb8();
+
+        assert_eq!(header, format!("\x1b]1337;File=inline=1;size={};width=1;height=1;preserveAspectRatio=0", png.len()));
+        assert_eq!((decoded.width(), decoded.height()), (2, 1));
+        assert_eq!(decoded.into_raw(), vec![255, 0, 0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::frame::{Image, Size};
+use crate::term::{self, remove_shared_memory, write_shared_memory, Medium, TermIo};
+use super::{rgb_bytes, Renderer};
+
+// Largest base64 payload the protocol allows in a single escape sequence
+const CHUNK_SIZE: usize = 4096;
+
+// Image id of the query that checks the first frame's medium, frames themselves use 1 and 2
+const CHECK_ID: u64 = 3;
+
+/// Kitty graphics protocol, each frame replaces the image drawn for the previous one
+pub struct Kitty {
+    cell_pixels: Size,
+    medium: Medium,
+    compress: bool,
+    frame: u64,
+    // Where the terminal's answer to the first out of band frame is read from
+    replies: Option<Box<dyn TermIo>>,
+}
+
+impl Kitty {
+    pub fn new(cell_pixels: Size, medium: Medium, compress: bool) -> Self {
+        Self {
+            cell_pixels,
+            medium,
+            compress,
+            frame: 0,
+            replies: None,
+        }
+    }
+
+    // Lets the first frame check that the terminal can actually read the medium, without it
+    // a medium the terminal rejects leaves the screen blank
+    pub fn set_replies(&mut self, replies: Box<dyn TermIo>) {
+        self.replies = Some(replies);
+    }
+
+    // Inline pixels go through the tty, so they are worth compressing
+    fn fall_back_to_direct(&mut self) {
+        self.medium = Medium::Direct;
+        self.compress = true;
+    }
+
+    // Has the terminal load `data` through the medium without showing it, true if it answered OK.
+    // The terminal removes what it read, so the real transmission stages the pixels again.
+    fn medium_accepted(&self, replies: &mut dyn TermIo, data: &[u8], size: Size, compression: &str) -> bool {
+        let Ok(payload) = self.write_medium(data) else {
+            return false;
+        };
+        let query = format!(
+            "\x1b_Ga=q,i={},f=24,t={},s={},v={}{};{}\x1b\\",
+            CHECK_ID, self.medium_key(), size.width, size.height, compression, STANDARD.encode(&payload),
+        );
+        let reply = format!("\x1b_Gi={};", CHECK_ID);
+
+        if replies.set_raw_mode(true).is_err() {
+            return false;
+        }
+
+        let answer = replies.write_all(query.as_bytes()).and_then(|_| {
+            term::read_replies(replies, term::PROBE_TIMEOUT, |answer| {
+                answer.find(&reply).is_some_and(|start| answer[start..].contains("\x1b\\"))
+            })
+        });
+
+        let _ = replies.set_raw_mode(false);
+
+        // Anything but OK, including no answer at all, means the pixels never arrived
+        match answer {
+            Ok(answer) if answer.contains(&format!("{}OK", reply)) => true,
+            _ => {
+                self.remove_medium();
+                false
+            },
+        }
+    }
+
+    fn medium_key(&self) -> char {
+        match self.medium {
+            Medium::Direct => 'd',
+            Medium::TempFile => 't',
+            Medium::SharedMemory => 's',
+        }
+    }
+
+    fn image_id(frame: u64) -> u64 {
+        // Alternating between two ids lets the new frame go up before the old one is deleted
+        frame % 2 + 1
+    }
+
+    fn medium_name(&self) -> String {
+        format!("tty-graphics-protocol-terminal-render-{}-{}", process::id(), self.frame % 2)
+    }
+
+    // Moves the pixels out of band, returns the payload naming where they went
+    fn write_medium(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let name = self.medium_name();
+
+        match self.medium {
+            Medium::Direct => Ok(data.to_vec()),
+            Medium::TempFile => {
+                let path = env::temp_dir().join(name);
+
+                File::create(&path)?.write_all(data)?;
+                Ok(path.to_string_lossy().into_owned().into_bytes())
+            },
+            Medium::SharedMemory => {
+                let name = format!("/{}", name);
+
+                write_shared_memory(&name, data)?;
+                Ok(name.into_bytes())
+            },
+        }
+    }
+
+    // Cleans up pixels staged for a terminal that never read them
+    fn remove_medium(&self) {
+        let name = self.medium_name();
+
+        match self.medium {
+            Medium::Direct => {},
+            Medium::TempFile => {
+                let _ = std::fs::remove_file(env::temp_dir().join(name));
+            },
+            Medium::SharedMemory => {
+                let _ = remove_shared_memory(&format!("/{}", name));
+            },
+        }
+    }
+}
+
+fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+impl Renderer for Kitty {
+    fn cell_pixels(&self) -> Size {
+        self.cell_pixels
+    }
+
+    fn square_pixels(&self) -> bool {
+        true
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let size = image.get_image_size();
+        let rgb = rgb_bytes(image, size.height);
+
+        let (data, compression) = match self.compress {
+            true => match compress(&rgb) {
+                Ok(compressed) => (compressed, ",o=z"),
+                Err(_) => (rgb, ""),
+            },
+            false => (rgb, ""),
+        };
+
+        if let Some(mut replies) = self.replies.take() {
+            if self.medium != Medium::Direct && !self.medium_accepted(replies.as_mut(), &data, size, compression) {
+                self.fall_back_to_direct();
+            }
+        }
+
+        let payload = match self.write_medium(&data) {
+            Ok(payload) => payload,
+            // Out of band transmission isn't working here, stay inline from now on
+            Err(_) => {
+                self.fall_back_to_direct();
+                data
+            }
+        };
+        let medium = self.medium_key();
+        let id = Self::image_id(self.frame);
+        let encoded = STANDARD.encode(&payload);
+        let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(CHUNK_SIZE).collect();
+
+        // C=1 keeps the cursor in place so a full screen image never scrolls
+        write!(
+            output,
+            "\x1b_Ga=T,q=2,C=1,f=24,t={},s={},v={},c={},r={},i={}{}",
+            medium, size.width, size.height, term_size.width, term_size.height, id, compression,
+        ).unwrap();
+
+        if chunks.is_empty() {
+            output.extend_from_slice(b";\x1b\\");
+        }
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let more = (index + 1 < chunks.len()) as u8;
+
+            // Follow up chunks only carry the continuation flag
+            match index {
+                0 => write!(output, ",m={};", more).unwrap(),
+                _ => write!(output, "\x1b_Gm={};", more).unwrap(),
+            }
+
+            output.extend_from_slice(chunk);
+            output.extend_from_slice(b"\x1b\\");
+        }
+
+        if self.frame > 0 {
+            write!(output, "\x1b_Ga=d,d=I,q=2,i={}\x1b\\", Self::image_id(self.frame - 1)).unwrap();
+        }
+
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::term::ScriptedTerminal;
+    use super::*;
+
+    const CELL_PIXELS: Size = Size { width: 10, height: 20 };
+    const TERM_SIZE: Size = Size { width: 2, height: 1 };
+
+    fn render_frames(medium: Medium, replies: &[u8], frames: usize) -> Vec<String> {
+        let mut kitty = Kitty::new(CELL_PIXELS, medium, false);
+        let image = Image::black(Size { width: 20, height: 20 });
+        let staged = kitty.medium_name();
+
+        kitty.set_replies(Box::new(ScriptedTerminal { replies: replies.to_vec(), written: Vec::new() }));
+
+        let outputs = (0..frames).map(|_| {
+            let mut output = Vec::new();
+
+            kitty.render(&image, TERM_SIZE, &mut output);
+            String::from_utf8(output).unwrap()
+        }).collect();
+
+        // No terminal reads the staged pixels here
+        let _ = std::fs::remove_file(env::temp_dir().join(&staged));
+        let _ = remove_shared_memory(&format!("/{}", staged));
+        outputs
+    }
+
+    #[test]
+    fn keeps_an_accepted_medium() {
+        let outputs = render_frames(Medium::TempFile, b"\x1b_Gi=3;OK\x1b\\", 1);
+
+        assert!(outputs[0].starts_with("\x1b_Ga=T,q=2,C=1,f=24,t=t,"));
+    }
+
+    #[test]
+    fn falls_back_when_the_terminal_rejects_the_medium() {
+        let outputs = render_frames(Medium::TempFile, b"\x1b_Gi=3;EBADF:bad file\x1b\\", 2);
+        let kitty = Kitty::new(CELL_PIXELS, Medium::TempFile, false);
+
+        assert!(outputs[0].starts_with("\x1b_Ga=T,q=2,C=1,f=24,t=d,"));
+        // Inline frames after the first one are compressed
+        assert!(outputs[1].contains("t=d") && outputs[1].contains(",o=z"));
+        assert!(!env::temp_dir().join(kitty.medium_name()).exists());
+    }
+
+    #[test]
+    fn falls_back_when_the_terminal_stays_silent() {
+        let outputs = render_frames(Medium::SharedMemory, b"", 1);
+
+        assert!(outputs[0].contains("t=d"));
+    }
+}
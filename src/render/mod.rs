@@ -0,0 +1,115 @@
+use crate::frame::{Image, Size};
+use crate::term::{Capabilities, ColorDepth, Medium};
+use crate::processing::dither::Matrix;
+use ansi::AnsiEncoder;
+
+pub mod ansi;
+pub mod ascii;
+pub mod block;
+pub mod braille;
+pub mod half_block;
+pub mod iterm;
+pub mod kitty;
+pub mod mosaic;
+pub mod sixel;
+pub mod xterm;
+
+/// A single terminal cell, `bg` of `None` leaves the terminal's default background
+#[derive(Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub fg: (u8, u8, u8),
+    pub bg: Option<(u8, u8, u8)>,
+}
+
+/// Turns a scaled image into the bytes that draw it, starting from the top left of the terminal
+pub trait Renderer {
+    /// How many image pixels end up in one terminal cell
+    fn cell_pixels(&self) -> Size;
+    /// True when `cell_pixels` is in screen pixels, so image pixels are drawn square
+    fn square_pixels(&self) -> bool {
+        false
+    }
+    /// Rows at the bottom of the terminal to leave empty, the image is fitted to the rows above them
+    fn reserved_rows(&self) -> u32 {
+        0
+    }
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>);
+}
+
+// Packed RGB of the first `height` rows
+pub fn rgb_bytes(image: &Image, height: u32) -> Vec<u8> {
+    let width = image.get_image_size().width;
+    let pixel_count = (width * height.min(image.get_image_size().height)) as usize;
+    let mut rgb = Vec::with_capacity(pixel_count * 3);
+
+    for pixel in image.get_pixel_data()[..pixel_count].iter() {
+        let (r, g, b) = pixel.get_color_raw();
+
+        rgb.extend_from_slice(&[r, g, b]);
+    }
+
+    rgb
+}
+
+// Cell size in screen pixels when the terminal can't tell us
+pub const DEFAULT_CELL_PIXELS: Size = Size { width: 10, height: 20 };
+
+// Brightness ramp for terminals without color
+pub const ASCII_SHADES: &str = " .:-=+*#%@";
+
+// Brightness a Braille dot has to exceed to light up, `braille=<0-255>` overrides it
+pub const BRAILLE_THRESHOLD: u8 = 64;
+
+// Out of band mediums skip compression, and check the first frame against the terminal's answer
+fn kitty(cell_pixels: Size, medium: Medium) -> Box<dyn Renderer> {
+    let mut kitty = kitty::Kitty::new(cell_pixels, medium, medium == Medium::Direct);
+
+    #[cfg(unix)]
+    if let Ok(tty) = crate::term::Tty::open() {
+        kitty.set_replies(Box::new(tty));
+    }
+
+    Box::new(kitty)
+}
+
+// Renderer for a `--mode` name, cell based ones write through `encoder` and pixel protocols use the probed cell size
+pub fn from_name(name: &str, capabilities: &Capabilities, encoder: AnsiEncoder) -> Option<Box<dyn Renderer>> {
+    let cell_pixels = capabilities.cell_pixels.unwrap_or(DEFAULT_CELL_PIXELS);
+    let kitty_medium = capabilities.kitty.unwrap_or(Medium::Direct);
+    let (name, threshold) = match name.split_once('=') {
+        Some((name @ ("braille" | "braille-mono"), value)) => (name, value.parse().ok()?),
+        Some(_) => return None,
+        None => (name, BRAILLE_THRESHOLD),
+    };
+
+    match name {
+        "auto" => from_name(select(capabilities), capabilities, encoder),
+        "ascii" => Some(Box::new(ascii::Ascii::new(ASCII_SHADES, encoder))),
+        "block" => Some(Box::new(block::Block::new(encoder))),
+        "half" => Some(Box::new(half_block::HalfBlock::new(encoder))),
+        "quadrant" => Some(Box::new(mosaic::Mosaic::new(mosaic::MosaicKind::Quadrant, encoder))),
+        "sextant" => Some(Box::new(mosaic::Mosaic::new(mosaic::MosaicKind::Sextant, encoder))),
+        "braille" => Some(Box::new(braille::Braille::new(braille::Activation::Threshold(threshold), false, encoder))),
+        "braille-dither" => Some(Box::new(braille::Braille::new(braille::Activation::Dither(Matrix::Bayer(4)), false, encoder))),
+        "braille-noise" => Some(Box::new(braille::Braille::new(braille::Activation::Dither(Matrix::BlueNoise), false, encoder))),
+        "braille-mono" => Some(Box::new(braille::Braille::new(braille::Activation::Threshold(threshold), true, encoder))),
+        "iterm" => Some(Box::new(iterm::ITerm::new(cell_pixels))),
+        "sixel" => Some(Box::new(sixel::Sixel::new(cell_pixels))),
+        "kitty" => Some(kitty(cell_pixels, kitty_medium)),
+        "kitty-file" => Some(kitty(cell_pixels, Medium::TempFile)),
+        "kitty-shm" => Some(kitty(cell_pixels, Medium::SharedMemory)),
+        _ => None,
+    }
+}
+
+// Mode name of the best renderer the terminal supports: kitty, sixel, iTerm2 images, then half blocks down to plain ASCII
+pub fn select(capabilities: &Capabilities) -> &'static str {
+    match capabilities {
+        Capabilities { kitty: Some(_), .. } => "kitty",
+        Capabilities { sixel: true, .. } => "sixel",
+        Capabilities { iterm: true, .. } => "iterm",
+        Capabilities { color_depth: ColorDepth::Monochrome, .. } => "ascii",
+        _ => "half",
+    }
+}
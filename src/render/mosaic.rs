@@ -0,0 +1,162 @@
+use crate::frame::{Image, Size};
+use crate::pixel::Vector2;
+use crate::vector2;
+use super::{ansi::AnsiEncoder, Cell, Renderer};
+
+// Indexed by a mask of foreground pixels, bit 0 top left, bit 1 top right, bit 2 bottom left, bit 3 bottom right
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MosaicKind {
+    // 2x2 block elements, U+2596 - U+259F
+    Quadrant,
+    // 2x3 block sextants from Symbols for Legacy Computing, U+1FB00 - U+1FB3B
+    Sextant,
+}
+
+impl MosaicKind {
+    fn glyph(&self, mask: usize) -> char {
+        match self {
+            MosaicKind::Quadrant => QUADRANTS[mask],
+            // The full left and right columns already exist as half blocks, so the range skips them
+            MosaicKind::Sextant => match mask {
+                0 => ' ',
+                21 => '▌',
+                42 => '▐',
+                63 => '█',
+                _ => {
+                    let skipped = (mask > 21) as u32 + (mask > 42) as u32;
+
+                    char::from_u32(0x1FB00 + mask as u32 - 1 - skipped).unwrap_or(' ')
+                }
+            },
+        }
+    }
+}
+
+/// Splits each 2x2 or 2x3 pixel group into the two colors that fit it best and draws the matching glyph
+pub struct Mosaic {
+    kind: MosaicKind,
+    encoder: AnsiEncoder,
+}
+
+impl Mosaic {
+    pub fn new(kind: MosaicKind, encoder: AnsiEncoder) -> Self {
+        Self {
+            kind,
+            encoder,
+        }
+    }
+}
+
+fn mean(colors: &[(u8, u8, u8)], mask: usize, selected: bool) -> ((u8, u8, u8), u32) {
+    let (mut r, mut g, mut b, mut count) = (0u32, 0u32, 0u32, 0u32);
+
+    for (index, color) in colors.iter().enumerate() {
+        if (mask >> index & 1 == 1) == selected {
+            r += color.0 as u32;
+            g += color.1 as u32;
+            b += color.2 as u32;
+            count += 1;
+        }
+    }
+
+    match count {
+        0 => ((0, 0, 0), 0),
+        _ => (((r / count) as u8, (g / count) as u8, (b / count) as u8), count),
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let dr = a.0 as i32 - b.0 as i32;
+    let dg = a.1 as i32 - b.1 as i32;
+    let db = a.2 as i32 - b.2 as i32;
+
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+// Tries every foreground mask and keeps the one with the lowest squared error
+fn best_partition(colors: &[(u8, u8, u8)]) -> (usize, (u8, u8, u8), (u8, u8, u8)) {
+    let mut best = (0, (0, 0, 0), (0, 0, 0));
+    let mut best_error = u32::MAX;
+
+    // The complement of a mask is the same split with the colors swapped, so the top bit stays clear
+    for mask in 0..1usize << (colors.len() - 1) {
+        let (fg, _) = mean(colors, mask, true);
+        let (bg, _) = mean(colors, mask, false);
+        let error: u32 = colors.iter().enumerate()
+            .map(|(index, &color)| match mask >> index & 1 {
+                1 => distance(color, fg),
+                _ => distance(color, bg),
+            })
+            .sum();
+
+        if error < best_error {
+            best_error = error;
+            best = (mask, fg, bg);
+        }
+    }
+
+    best
+}
+
+impl Renderer for Mosaic {
+    fn cell_pixels(&self) -> Size {
+        match self.kind {
+            MosaicKind::Quadrant => Size { width: 2, height: 2 },
+            MosaicKind::Sextant => Size { width: 2, height: 3 },
+        }
+    }
+
+    fn render(&mut self, image: &Image, term_size: Size, output: &mut Vec<u8>) {
+        let cell_pixels = self.cell_pixels();
+        let mut cells = Vec::with_capacity((term_size.width * term_size.height) as usize);
+        let mut colors = Vec::with_capacity(6);
+
+        for row in 0..term_size.height {
+            for column in 0..term_size.width {
+                colors.clear();
+
+                for dy in 0..cell_pixels.height {
+                    for dx in 0..cell_pixels.width {
+                        let position = vector2!(column * 2 + dx, row * cell_pixels.height + dy);
+
+                        colors.push(image.get_pixel(position).get_color_raw());
+                    }
+                }
+
+                let (mask, fg, bg) = best_partition(&colors);
+
+                cells.push(Cell { character: self.kind.glyph(mask), fg, bg: Some(bg) });
+            }
+        }
+
+        self.encoder.encode(&cells, term_size.width, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sextants_skip_the_half_blocks() {
+        let glyph = |mask| MosaicKind::Sextant.glyph(mask);
+
+        assert_eq!([glyph(0), glyph(21), glyph(42), glyph(63)], [' ', '▌', '▐', '█']);
+        // Before, between and after the two skipped left and right column codepoints
+        assert_eq!([glyph(1), glyph(20), glyph(22), glyph(41), glyph(43), glyph(62)], [
+            '\u{1FB00}', '\u{1FB13}', '\u{1FB14}', '\u{1FB27}', '\u{1FB28}', '\u{1FB3B}',
+        ]);
+
+        let mut codepoints: Vec<u32> = (1..63)
+            .filter(|&mask| mask != 21 && mask != 42)
+            .map(|mask| glyph(mask) as u32)
+            .collect();
+
+        codepoints.dedup();
+        assert_eq!(codepoints, (0x1FB00..=0x1FB3B).collect::<Vec<u32>>());
+    }
+}
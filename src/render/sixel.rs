@@ -0,0 +1,311 @@
+use std::io::Write;
+
+use crate::frame::{Image, Size};
+use crate::processing::palette::{Metric, PaletteBuilder, PaletteMatcher, Quantizer};
+use super::Renderer;
+
+// Color registers, the most terminals offer
+const REGISTERS: usize = 256;
+
+/// DEC Sixel graphics, true pixel output for xterm, foot, mlterm, WezTerm and friends
+pub struct Sixel {
+    cell_pixels: Size,
+    palette: PaletteBuilder,
+}
+
+impl Sixel {
+    // `cell_pixels` is the size of one terminal cell in screen pixels
+    pub fn new(cell_pixels: Size) -> Self {
+        Self {
+            cell_pixels,
+            palette: PaletteBuilder::new(Quantizer::MedianCut, REGISTERS),
+        }
+    }
+}
+
+// Sixel colors are given in percent
+fn percent(value: u8) -> u32 {
+    (value as u32 * 100 + 127) / 255
+}
+
+fn write_run(output: &mut Vec<u8>, sixel: u8, count: usize) {
+    match count {
+        0 => {},
+        1..=3 => output.extend(std::iter::repeat_n(sixel, count)),
+        _ => write!(output, "!{}{}", count, sixel as char).unwrap(),
+    }
+}
+
+/// Encodes `height` rows of the image as a complete sixel sequence, one register per palette entry
+pub fn encode(image: &Image, height: u32, palette: Vec<(u8, u8, u8)>, output: &mut Vec<u8>) {
+    let width = image.get_image_size().width;
+    let height = height.min(image.get_image_size().height);
+    let mut matcher = PaletteMatcher::new(palette, Metric::Oklab);
+    let indices: Vec<usize> = image.get_pixel_data()[..(width * height) as usize].iter()
+        .map(|pixel| matcher.nearest(pixel.get_color_raw()) as usize)
+        .collect();
+    let register_count = REGISTERS;
+    let mut used = vec![false; register_count];
+
+    for &index in indices.iter() {
+        used[index] = true;
+    }
+
+    // Pixel aspect 1:1 and an explicit raster size so terminals don't have to guess
+    write!(output, "\x1bP0;1;0q\"1;1;{};{}", width, height).unwrap();
+
+    for (index, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+        let (r, g, b) = matcher.color(index as u8);
+
+        write!(output, "#{};2;{};{};{}", index, percent(r), percent(g), percent(b)).unwrap();
+    }
+
+    let mut band = vec![0u8; register_count * width as usize];
+    let mut in_band = vec![false; register_count];
+    let mut band_colors = Vec::new();
+
+    for band_top in (0..height).step_by(6) {
+        band_colors.clear();
+
+        for y in band_top..(band_top + 6).min(height) {
+            let bit = 1 << (y - band_top);
+
+            for x in 0..width {
+                let index = indices[(y * width + x) as usize];
+
+                if !in_band[index] {
+                    in_band[index] = true;
+                    band_colors.push(index);
+                }
+
+                band[index * width as usize + x as usize] |= bit;
+            }
+        }
+
+        for (color_number, &index) in band_colors.iter().enumerate() {
+            let offset = index * width as usize;
+            let row = &mut band[offset..offset + width as usize];
+            let mut run = (0u8, 0usize);
+
+            in_band[index] = false;
+
+            if color_number > 0 {
+                // Back to the start of the band to overlay the next color
+                output.push(b'$');
+            }
+
+            write!(output, "#{}", index).unwrap();
+
+            for bits in row.iter_mut() {
+                let sixel = 0x3f + *bits;
+
+                if sixel != run.0 {
+                    write_run(output, run.0, run.1);
+                    run = (sixel, 0);
+                }
+
+                run.1 += 1;
+                *bits = 0;
+            }
+
+            write_run(output, run.0, run.1);
+        }
+
+        output.push(b'-');
+    }
+
+    output.extend_from_slice(b"\x1b\\");
+}
+
+impl Renderer for Sixel {
+    fn cell_pixels(&self) -> Size {
+        self.cell_pixels
+    }
+
+    fn square_pixels(&self) -> bool {
+        true
+    }
+
+    // Keep the last row free, drawing into it would scroll the terminal
+    fn reserved_rows(&self) -> u32 {
+        1
+    }
+
+    fn render(&mut self, image: &Image, _term_size: Size, output: &mut Vec<u8>) {
+        let palette = self.palette.build(image);
+
+        encode(image, image.get_image_size().height, palette, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::Color;
+    use crate::pixel::{PxData, Vector2};
+    use crate::pixel;
+    use super::*;
+
+    const RED: (u8, u8, u8) = (255, 0, 0);
+    const BLUE: (u8, u8, u8) = (0, 0, 255);
+
+    fn image(width: u32, height: u32, color: impl Fn(u32, u32) -> (u8, u8, u8)) -> Image {
+        let mut pixel_data = Vec::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = color(x, y);
+
+                pixel_data.push(pixel!(r, g, b, x, y));
+            }
+        }
+
+        Image::new(pixel_data, Size { width, height }, 3)
+    }
+
+    // Register of every pixel, register definitions in percent and the raster size
+    type Decoded = (Vec<Option<usize>>, Vec<(usize, [usize; 3])>, (usize, usize));
+
+    // Just enough of a sixel decoder to read back what `encode` writes
+    fn decode(data: &[u8]) -> Decoded {
+        let body = data.strip_prefix(b"\x1bP0;1;0q\"").unwrap().strip_suffix(b"\x1b\\").unwrap();
+        let number = |index: &mut usize| {
+            let start = *index;
+
+            while body[*index].is_ascii_digit() {
+                *index += 1;
+            }
+
+            std::str::from_utf8(&body[start..*index]).unwrap().parse::<usize>().unwrap()
+        };
+        let mut index = 0;
+        let mut raster = [0; 4];
+
+        for (field, value) in raster.iter_mut().enumerate() {
+            *value = number(&mut index);
+
+            if field < 3 {
+                assert_eq!(body[index], b';');
+                index += 1;
+            }
+        }
+
+        let (width, height) = (raster[2], raster[3]);
+        let mut pixels = vec![None; width * height];
+        let mut registers = Vec::new();
+        let (mut x, mut band, mut register) = (0, 0, 0);
+
+        while index < body.len() {
+            let byte = body[index];
+
+            index += 1;
+
+            let count = match byte {
+                b'#' => {
+                    register = number(&mut index);
+
+                    // `;2;r;g;b` defines the register in RGB percent
+                    if body.get(index) == Some(&b';') {
+                        index += 2;
+
+                        let mut color = [0; 3];
+
+                        for channel in color.iter_mut() {
+                            index += 1;
+                            *channel = number(&mut index);
+                        }
+
+                        registers.push((register, color));
+                    }
+
+                    continue;
+                },
+                b'$' => {
+                    x = 0;
+                    continue;
+                },
+                b'-' => {
+                    (x, band) = (0, band + 6);
+                    continue;
+                },
+                b'!' => number(&mut index),
+                _ => {
+                    index -= 1;
+                    1
+                },
+            };
+            let bits = body[index] - 0x3f;
+
+            index += 1;
+
+            for _ in 0..count {
+                for bit in 0..6 {
+                    if bits & 1 << bit != 0 {
+                        assert!(pixels[(band + bit) * width + x].replace(register).is_none(), "pixel drawn twice");
+                    }
+                }
+
+                x += 1;
+            }
+        }
+
+        (pixels, registers, (width, height))
+    }
+
+    #[test]
+    fn snapshot() {
+        let image = image(6, 2, |x, y| match x < 4 || (x == 4 && y == 1) {
+            true => RED,
+            false => BLUE,
+        });
+        let mut output = Vec::new();
+
+        encode(&image, 2, vec![RED, BLUE], &mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\x1bP0;1;0q\"1;1;6;2#0;2;100;0;0#1;2;0;0;100#0!4BA?$#1!4?@B-\x1b\\",
+        );
+    }
+
+    #[test]
+    fn decodes_back_to_the_palette_indices() {
+        let palette = vec![RED, (0, 255, 0), BLUE, (255, 255, 255), (0, 0, 0)];
+        let index = |x: u32, y: u32| ((x / 3 + y * 2) % 4) as usize;
+        let image = image(13, 11, |x, y| palette[index(x, y)]);
+        let mut output = Vec::new();
+
+        encode(&image, 11, palette.clone(), &mut output);
+
+        let (pixels, registers, size) = decode(&output);
+
+        assert_eq!(size, (13, 11));
+
+        for y in 0..11 {
+            for x in 0..13 {
+                assert_eq!(pixels[(y * 13 + x) as usize], Some(index(x, y)), "pixel {} {}", x, y);
+            }
+        }
+
+        // Black is never used so it never gets a register
+        assert_eq!(registers.len(), 4);
+
+        for (register, color) in registers {
+            let (r, g, b) = palette[register];
+
+            assert_eq!(color, [r, g, b].map(|channel| percent(channel) as usize));
+        }
+    }
+
+    #[test]
+    fn height_limits_the_rows() {
+        let image = image(4, 8, |_, _| RED);
+        let mut output = Vec::new();
+
+        encode(&image, 3, vec![RED], &mut output);
+
+        let (pixels, _, size) = decode(&output);
+
+        assert_eq!(size, (4, 3));
+        assert!(pixels.iter().all(|pixel| *pixel == Some(0)));
+    }
+}
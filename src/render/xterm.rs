@@ -0,0 +1,47 @@
+// xterm's default values for the 16 ANSI colors, real terminals theme these so matches are approximate
+pub const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// Channel values of the 6x6x6 color cube at indices 16 - 231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// Index of the first entry of `palette_256`, the ANSI colors below it follow the terminal theme
+pub const PALETTE_256_START: u8 = 16;
+
+/// The fixed part of the xterm 256 color palette, indices 16 - 255: color cube, then a 24 step gray ramp.
+/// The 16 ANSI colors are left out, themes redefine them so matching against them picks the wrong color.
+pub fn palette_256() -> Vec<(u8, u8, u8)> {
+    let mut palette = Vec::with_capacity(240);
+
+    for r in CUBE_LEVELS {
+        for g in CUBE_LEVELS {
+            for b in CUBE_LEVELS {
+                palette.push((r, g, b));
+            }
+        }
+    }
+
+    for step in 0..24 {
+        let gray = 8 + step * 10;
+
+        palette.push((gray, gray, gray));
+    }
+
+    palette
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+use std::{thread, time::Duration};
+use crossterm::terminal;
+
+use crate::frame::{ColorFormat, Size};
+use super::{FrameSource, SourceError};
+
+/// Still image decoded from disk (PNG, JPEG, BMP or PPM)
+pub struct ImageFile {
+    buffer: Vec<u8>,
+    size: Size,
+    watch_resize: bool,
+    sent: bool,
+    term_size: Option<(u16, u16)>,
+}
+
+impl ImageFile {
+    // With `watch_resize` the image is handed out again every time the terminal changes size
+    pub fn open<P: AsRef<Path>>(path: P, watch_resize: bool) -> Result<Self, SourceError> {
+        let decoded = image::open(path)?.to_rgb8();
+        let size = Size { width: decoded.width(), height: decoded.height() };
+
+        Ok(Self {
+            buffer: decoded.into_raw(),
+            size,
+            watch_resize,
+            sent: false,
+            term_size: None,
+        })
+    }
+
+    fn wait_for_resize(&mut self, last: (u16, u16)) -> Result<(), SourceError> {
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            let current = terminal::size()?;
+
+            if current != last {
+                self.term_size = Some(current);
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl FrameSource for ImageFile {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        // The terminal is only asked for its size when resizes are watched, so a plain open works without one
+        match (self.sent, self.term_size) {
+            (false, _) => {
+                self.sent = true;
+
+                if self.watch_resize {
+                    self.term_size = Some(terminal::size()?);
+                }
+            },
+            (true, None) => {
+                return Ok(None);
+            },
+            (true, Some(last)) => {
+                self.wait_for_resize(last)?;
+            }
+        }
+
+        Ok(Some(self.buffer.clone()))
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgb8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_a_png() {
+        let path = std::env::temp_dir().join(format!("terminal-render-image-{}.png", std::process::id()));
+        let pixels = image::RgbImage::from_fn(3, 2, |x, y| image::Rgb([x as u8 * 100, y as u8 * 200, 7]));
+
+        pixels.save(&path).unwrap();
+
+        let source = ImageFile::open(&path, false);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let mut source = source.unwrap();
+        let size = source.size();
+
+        assert_eq!((size.width, size.height), (3, 2));
+        assert!(matches!(source.color_format(), ColorFormat::Rgb8));
+        assert_eq!(source.next_frame().unwrap().unwrap(), vec![
+            0, 0, 7, 100, 0, 7, 200, 0, 7,
+            0, 200, 7, 100, 200, 7, 200, 200, 7,
+        ]);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{thread, time::Duration, time::Instant};
+use crate::frame::{ColorFormat, Size};
+
+pub mod image_file;
+pub mod pattern;
+pub mod video;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+pub type SourceError = Box<dyn std::error::Error>;
+
+/// Anything that can feed raw frame buffers into a `Canvas`
+pub trait FrameSource {
+    /// Blocks until the next frame is ready, returns `None` once the source has ended
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError>;
+    /// Size of the last frame returned by `next_frame`
+    fn size(&self) -> Size;
+    fn color_format(&self) -> ColorFormat;
+}
+
+/// Keeps frames from being handed out faster than the stream's frame rate
+pub(crate) struct Pacer {
+    frame_time: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl Pacer {
+    pub fn new(fps: f64) -> Self {
+        let frame_time = match fps > 0.0 {
+            true => Some(Duration::from_secs_f64(1.0 / fps)),
+            false => None,
+        };
+
+        Self {
+            frame_time,
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn wait(&mut self) {
+        if let Some(frame_time) = self.frame_time {
+            let now = Instant::now();
+
+            match self.next_frame > now {
+                true => thread::sleep(self.next_frame - now),
+                // Running behind, don't try to catch up with a burst of frames
+                false => self.next_frame = now,
+            }
+
+            self.next_frame += frame_time;
+        }
+    }
+}
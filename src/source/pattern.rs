@@ -0,0 +1,309 @@
+use std::f64::consts::PI;
+
+use crate::frame::{ColorFormat, FrameData, Size};
+use super::{FrameSource, Pacer, SourceError};
+
+// 3x5 bitmaps for the digits in the text grid, one row per entry, most significant bit on the left
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+// 75% bars, left to right
+const BARS: [(u8, u8, u8); 7] = [
+    (191, 191, 191),
+    (191, 191, 0),
+    (0, 191, 191),
+    (0, 191, 0),
+    (191, 0, 191),
+    (191, 0, 0),
+    (0, 0, 191),
+];
+
+#[derive(Clone, Copy)]
+pub enum Pattern {
+    ColorBars,
+    // Gray, red, green and blue ramps stacked as horizontal bands
+    Gradient,
+    Checkerboard { cell: u32 },
+    MovingCircle,
+    ZonePlate,
+    TextGrid { cell: u32 },
+}
+
+impl Pattern {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bars" => Some(Pattern::ColorBars),
+            "gradient" => Some(Pattern::Gradient),
+            "checkerboard" => Some(Pattern::Checkerboard { cell: 8 }),
+            "circle" => Some(Pattern::MovingCircle),
+            "zoneplate" => Some(Pattern::ZonePlate),
+            "grid" => Some(Pattern::TextGrid { cell: 32 }),
+            _ => None,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32, size: Size, frame: u64) -> (u8, u8, u8) {
+        let (width, height) = (size.width.max(1), size.height.max(1));
+
+        match *self {
+            Pattern::ColorBars => color_bars(x, y, width, height),
+            Pattern::Gradient => {
+                let value = (x as f64 / (width - 1).max(1) as f64 * 255.0).round() as u8;
+
+                match y * 4 / height {
+                    0 => (value, value, value),
+                    1 => (value, 0, 0),
+                    2 => (0, value, 0),
+                    _ => (0, 0, value),
+                }
+            },
+            Pattern::Checkerboard { cell } => {
+                let cell = cell.max(1);
+
+                match (x / cell + y / cell) % 2 {
+                    0 => (255, 255, 255),
+                    _ => (0, 0, 0),
+                }
+            },
+            Pattern::MovingCircle => {
+                let angle = frame as f64 * 2.0 * PI / 120.0;
+                let orbit = width.min(height) as f64 / 4.0;
+                let radius = width.min(height) as f64 / 8.0;
+                let center_x = width as f64 / 2.0 + orbit * angle.cos();
+                let center_y = height as f64 / 2.0 + orbit * angle.sin();
+                let distance = (x as f64 + 0.5 - center_x).hypot(y as f64 + 0.5 - center_y);
+
+                match distance <= radius {
+                    true => (255, 255, 255),
+                    false => (0, 0, 0),
+                }
+            },
+            Pattern::ZonePlate => {
+                // Spatial frequency rises linearly from the center up to Nyquist at the shorter edge
+                let dx = x as f64 - width as f64 / 2.0;
+                let dy = y as f64 - height as f64 / 2.0;
+                let k = PI / width.min(height) as f64;
+                let phase = frame as f64 * PI / 30.0;
+                let value = (127.5 + 127.5 * (k * (dx * dx + dy * dy) + phase).cos()).round() as u8;
+
+                (value, value, value)
+            },
+            Pattern::TextGrid { cell } => text_grid(x, y, cell.max(8), width),
+        }
+    }
+}
+
+fn color_bars(x: u32, y: u32, width: u32, height: u32) -> (u8, u8, u8) {
+    let bar = (x * 7 / width) as usize;
+
+    match y * 12 / height {
+        0..=7 => BARS[bar],
+        // Reverse blue bars
+        8 => match bar % 2 {
+            0 => BARS[6 - bar],
+            _ => (0, 0, 0),
+        },
+        // -I, white, +Q, black and the pluge below 5/7 of the width
+        _ => match x * 28 / width {
+            0..=4 => (0, 33, 76),
+            5..=9 => (255, 255, 255),
+            10..=14 => (50, 0, 106),
+            15..=19 => (0, 0, 0),
+            20 => (0, 0, 0),
+            21 => (19, 19, 19),
+            22 => (9, 9, 9),
+            _ => (0, 0, 0),
+        },
+    }
+}
+
+// White grid lines with every cell labelled by its index
+fn text_grid(x: u32, y: u32, cell: u32, width: u32) -> (u8, u8, u8) {
+    if x.is_multiple_of(cell) || y.is_multiple_of(cell) {
+        return (255, 255, 255);
+    }
+
+    let columns = width.div_ceil(cell);
+    let index = (y / cell) * columns + x / cell;
+    let digits = index.to_string().into_bytes();
+    let (local_x, local_y) = (x % cell, y % cell);
+
+    // Digits are drawn at 2px per bit starting two pixels in from the cell corner
+    if local_x < 2 || !(2..12).contains(&local_y) {
+        return (0, 0, 0);
+    }
+
+    let (glyph_x, glyph_y) = ((local_x - 2) / 2, (local_y - 2) / 2);
+    let (digit, column) = ((glyph_x / 4) as usize, glyph_x % 4);
+
+    match digit < digits.len() && column < 3 {
+        true => {
+            let row = DIGITS[(digits[digit] - b'0') as usize][glyph_y as usize];
+
+            match row >> (2 - column) & 1 {
+                1 => (255, 255, 0),
+                _ => (0, 0, 0),
+            }
+        },
+        false => (0, 0, 0),
+    }
+}
+
+/// Deterministic generated frames at a fixed resolution and frame rate
+pub struct PatternSource {
+    pattern: Pattern,
+    size: Size,
+    frame: u64,
+    pacer: Pacer,
+}
+
+impl PatternSource {
+    pub fn new(pattern: Pattern, size: Size, fps: f64) -> Self {
+        Self {
+            pattern,
+            size,
+            frame: 0,
+            pacer: Pacer::new(fps),
+        }
+    }
+
+    pub fn buffer(&self, frame: u64) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity((self.size.width * self.size.height * 3) as usize);
+
+        for y in 0..self.size.height {
+            for x in 0..self.size.width {
+                let (r, g, b) = self.pattern.pixel(x, y, self.size, frame);
+
+                buffer.extend_from_slice(&[r, g, b]);
+            }
+        }
+
+        buffer
+    }
+
+    pub fn frame_data(&self, frame: u64) -> FrameData {
+        let mut frame_data = FrameData::default();
+
+        frame_data.set_color_format(ColorFormat::Rgb8);
+        frame_data.set_raw_data(self.buffer(frame), self.size);
+        frame_data
+    }
+}
+
+impl FrameSource for PatternSource {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        let buffer = self.buffer(self.frame);
+
+        self.pacer.wait();
+        self.frame += 1;
+        Ok(Some(buffer))
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgb8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pixel::Vector2;
+    use crate::vector2;
+    use super::*;
+
+    const SIZE: Size = Size { width: 140, height: 120 };
+
+    #[test]
+    fn color_bars_switch_at_each_seventh() {
+        let bars = Pattern::ColorBars;
+
+        assert_eq!(bars.pixel(19, 0, SIZE, 0), BARS[0]);
+        assert_eq!(bars.pixel(20, 0, SIZE, 0), BARS[1]);
+        assert_eq!(bars.pixel(119, 79, SIZE, 0), BARS[5]);
+        assert_eq!(bars.pixel(139, 79, SIZE, 0), BARS[6]);
+        // Reverse blue bars below two thirds of the height
+        assert_eq!(bars.pixel(0, 80, SIZE, 0), BARS[6]);
+        assert_eq!(bars.pixel(20, 80, SIZE, 0), (0, 0, 0));
+        // -I, white and the pluge in the bottom row
+        assert_eq!(bars.pixel(0, 119, SIZE, 0), (0, 33, 76));
+        assert_eq!(bars.pixel(25, 119, SIZE, 0), (255, 255, 255));
+        assert_eq!(bars.pixel(105, 119, SIZE, 0), (19, 19, 19));
+    }
+
+    #[test]
+    fn gradient_covers_the_full_range() {
+        let gradient = Pattern::Gradient;
+
+        assert_eq!(gradient.pixel(0, 0, SIZE, 0), (0, 0, 0));
+        assert_eq!(gradient.pixel(139, 0, SIZE, 0), (255, 255, 255));
+        assert_eq!(gradient.pixel(139, 30, SIZE, 0), (255, 0, 0));
+        assert_eq!(gradient.pixel(139, 60, SIZE, 0), (0, 255, 0));
+        assert_eq!(gradient.pixel(139, 119, SIZE, 0), (0, 0, 255));
+    }
+
+    #[test]
+    fn checkerboard_parity() {
+        let checkerboard = Pattern::Checkerboard { cell: 8 };
+
+        assert_eq!(checkerboard.pixel(0, 0, SIZE, 0), (255, 255, 255));
+        assert_eq!(checkerboard.pixel(7, 7, SIZE, 0), (255, 255, 255));
+        assert_eq!(checkerboard.pixel(8, 0, SIZE, 0), (0, 0, 0));
+        assert_eq!(checkerboard.pixel(0, 8, SIZE, 0), (0, 0, 0));
+        assert_eq!(checkerboard.pixel(8, 8, SIZE, 0), (255, 255, 255));
+    }
+
+    #[test]
+    fn zone_plate_center_follows_the_phase() {
+        let zone_plate = Pattern::ZonePlate;
+
+        assert_eq!(zone_plate.pixel(70, 60, SIZE, 0), (255, 255, 255));
+        assert_eq!(zone_plate.pixel(70, 60, SIZE, 30), (0, 0, 0));
+        assert_eq!(zone_plate.pixel(70, 60, SIZE, 60), (255, 255, 255));
+    }
+
+    #[test]
+    fn circle_starts_right_of_center() {
+        let circle = Pattern::MovingCircle;
+
+        // Orbit of 30 and radius of 15 around the center of the frame
+        assert_eq!(circle.pixel(100, 60, SIZE, 0), (255, 255, 255));
+        assert_eq!(circle.pixel(70, 60, SIZE, 0), (0, 0, 0));
+        assert_eq!(circle.pixel(70, 90, SIZE, 30), (255, 255, 255));
+    }
+
+    #[test]
+    fn text_grid_labels_cells() {
+        let grid = Pattern::TextGrid { cell: 32 };
+
+        assert_eq!(grid.pixel(0, 5, SIZE, 0), (255, 255, 255));
+        assert_eq!(grid.pixel(5, 32, SIZE, 0), (255, 255, 255));
+        // Top row of the "1" in the second cell only lights its middle column
+        assert_eq!(grid.pixel(36, 2, SIZE, 0), (255, 255, 0));
+        assert_eq!(grid.pixel(34, 2, SIZE, 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn frames_are_deterministic() {
+        let source = PatternSource::new(Pattern::ZonePlate, SIZE, 0.0);
+        let frame_data = source.frame_data(7);
+
+        assert!(source.buffer(7) == source.buffer(7));
+        assert_eq!(
+            frame_data.get_image().get_pixel(vector2!(13, 21)).get_color_raw(),
+            Pattern::ZonePlate.pixel(13, 21, SIZE, 7),
+        );
+    }
+}
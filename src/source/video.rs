@@ -0,0 +1,255 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+
+use crate::frame::{ColorFormat, Size};
+use super::{FrameSource, Pacer, SourceError};
+
+// Fills `buffer` completely, `false` if the stream ended cleanly before the first byte
+fn read_frame<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<bool, SourceError> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err("Stream ended in the middle of a frame".into()),
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {},
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Headerless RGBA8 frames, e.g. `ffmpeg -f rawvideo -pix_fmt rgba -`
+pub struct RawVideo<R: Read> {
+    reader: R,
+    size: Size,
+    pacer: Pacer,
+}
+
+impl<R: Read> RawVideo<R> {
+    pub fn new(reader: R, width: u32, height: u32, fps: f64) -> Self {
+        Self {
+            reader,
+            size: Size { width, height },
+            pacer: Pacer::new(fps),
+        }
+    }
+}
+
+impl<R: Read> FrameSource for RawVideo<R> {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        let multiplier = self.color_format().multiplier() as usize;
+        let mut buffer = vec![0; self.size.width as usize * self.size.height as usize * multiplier];
+
+        if !read_frame(&mut self.reader, &mut buffer)? {
+            return Ok(None);
+        }
+
+        self.pacer.wait();
+        Ok(Some(buffer))
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgba8
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Chroma {
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Chroma {
+    // Chroma plane size for a luma plane of `size`
+    fn plane_size(&self, size: Size) -> Size {
+        let (width, height) = (size.width, size.height);
+
+        match self {
+            Chroma::C420 => Size { width: width.div_ceil(2), height: height.div_ceil(2) },
+            Chroma::C422 => Size { width: width.div_ceil(2), height },
+            Chroma::C444 => size,
+            Chroma::Mono => Size { width: 0, height: 0 },
+        }
+    }
+}
+
+/// YUV4MPEG2 stream, converted to RGB with BT.601 limited range coefficients
+pub struct Y4m<R: Read> {
+    reader: BufReader<R>,
+    size: Size,
+    chroma: Chroma,
+    pacer: Pacer,
+}
+
+impl<R: Read> Y4m<R> {
+    pub fn new(reader: R) -> Result<Self, SourceError> {
+        let mut reader = BufReader::new(reader);
+        let mut header = String::new();
+
+        reader.read_line(&mut header)?;
+
+        let mut fields = header.split_whitespace();
+
+        if fields.next() != Some("YUV4MPEG2") {
+            return Err("Not a YUV4MPEG2 stream".into());
+        }
+
+        let mut size = Size::default();
+        let mut chroma = Chroma::C420;
+        let mut fps = 0.0;
+
+        for field in fields {
+            let (tag, value) = field.split_at_checked(1).ok_or("Invalid Y4M header field")?;
+
+            match tag {
+                "W" => size.width = value.parse()?,
+                "H" => size.height = value.parse()?,
+                "F" => {
+                    let (numerator, denominator) = value.split_once(':').ok_or("Invalid frame rate")?;
+                    let denominator: f64 = denominator.parse()?;
+
+                    if denominator > 0.0 {
+                        fps = numerator.parse::<f64>()? / denominator;
+                    }
+                },
+                "C" => {
+                    // Only the 8-bit layouts, the high bit depth ones share the prefixes but use two bytes per sample
+                    chroma = match value {
+                        "420jpeg" | "420paldv" | "420mpeg2" | "420" => Chroma::C420,
+                        "422" => Chroma::C422,
+                        "444" => Chroma::C444,
+                        "mono" => Chroma::Mono,
+                        _ => return Err(format!("Unsupported Y4M colorspace {}", value).into()),
+                    };
+                },
+                _ => {}
+            }
+        }
+
+        if size.width == 0 || size.height == 0 {
+            return Err("Y4M header is missing the frame size".into());
+        }
+
+        Ok(Self {
+            reader,
+            size,
+            chroma,
+            pacer: Pacer::new(fps),
+        })
+    }
+
+    fn to_rgb(&self, planes: &[u8]) -> Vec<u8> {
+        let (width, height) = (self.size.width as usize, self.size.height as usize);
+        let chroma_size = self.chroma.plane_size(self.size);
+        let chroma_width = chroma_size.width as usize;
+        let chroma_length = chroma_width * chroma_size.height as usize;
+        let (luma, chroma) = planes.split_at(width * height);
+        let (u_plane, v_plane) = chroma.split_at(chroma_length);
+        let mut rgb = Vec::with_capacity(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let luma = luma[y * width + x] as f64;
+                let (u, v) = match self.chroma {
+                    Chroma::Mono => (128.0, 128.0),
+                    _ => {
+                        let chroma_x = x * chroma_width / width;
+                        let chroma_y = y * chroma_size.height as usize / height;
+                        let index = chroma_y * chroma_width + chroma_x;
+
+                        (u_plane[index] as f64, v_plane[index] as f64)
+                    }
+                };
+                let c = (luma - 16.0) * 1.164;
+                let d = u - 128.0;
+                let e = v - 128.0;
+
+                rgb.push((c + 1.596 * e).round().clamp(0.0, 255.0) as u8);
+                rgb.push((c - 0.392 * d - 0.813 * e).round().clamp(0.0, 255.0) as u8);
+                rgb.push((c + 2.017 * d).round().clamp(0.0, 255.0) as u8);
+            }
+        }
+
+        rgb
+    }
+}
+
+impl<R: Read> FrameSource for Y4m<R> {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        let mut frame_header = String::new();
+
+        if self.reader.read_line(&mut frame_header)? == 0 {
+            return Ok(None);
+        }
+
+        if !frame_header.starts_with("FRAME") {
+            return Err("Invalid Y4M frame header".into());
+        }
+
+        let chroma_size = self.chroma.plane_size(self.size);
+        let luma_length = (self.size.width * self.size.height) as usize;
+        let chroma_length = (chroma_size.width * chroma_size.height) as usize;
+        let mut planes = vec![0; luma_length + chroma_length * 2];
+
+        if !read_frame(&mut self.reader, &mut planes)? {
+            return Err("Stream ended in the middle of a frame".into());
+        }
+
+        self.pacer.wait();
+        Ok(Some(self.to_rgb(&planes)))
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgb8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_tolerates_extra_spaces() {
+        let y4m = Y4m::new(&b"YUV4MPEG2  W4 H2 F30:1 C444 \n"[..]).unwrap();
+
+        assert_eq!((y4m.size.width, y4m.size.height), (4, 2));
+        assert!(y4m.chroma == Chroma::C444);
+    }
+
+    #[test]
+    fn header_errors_instead_of_panicking() {
+        assert!(Y4m::new(&b"YUV4MPEG2 W4 H2 \xc3\xa9\n"[..]).is_err());
+        assert!(Y4m::new(&b"YUV4MPEG2 W4\n"[..]).is_err());
+        assert!(Y4m::new(&b"YUV4MPEG2 Wx H2\n"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_high_bit_depth_colorspaces() {
+        let error = Y4m::new(&b"YUV4MPEG2 W4 H2 C420p10\n"[..]).err().unwrap().to_string();
+
+        assert!(error.contains("Unsupported Y4M colorspace 420p10"), "{}", error);
+        assert!(Y4m::new(&b"YUV4MPEG2 W4 H2 C444alpha\n"[..]).is_err());
+        assert!(Y4m::new(&b"YUV4MPEG2 W4 H2 C420paldv\n"[..]).is_ok());
+    }
+
+    #[test]
+    fn decodes_a_gray_frame() {
+        let stream = [&b"YUV4MPEG2 W2 H2 F25:1 C420\nFRAME\n"[..], &[235, 235, 235, 235, 128, 128]].concat();
+        let mut y4m = Y4m::new(&stream[..]).unwrap();
+
+        assert_eq!(y4m.next_frame().unwrap(), Some(vec![255; 12]));
+        assert_eq!(y4m.next_frame().unwrap(), None);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use windows_capture::{
+    capture::{CaptureControl, GraphicsCaptureApiHandler},
+    frame::Frame,
+    graphics_capture_api::InternalCaptureControl,
+    window::Window,
+    settings::{self, CursorCaptureSettings, DrawBorderSettings, Settings},
+};
+
+use crate::frame::{ColorFormat, Size};
+use super::{FrameSource, SourceError};
+
+struct Capture {
+    sender: SyncSender<(Vec<u8>, Size)>,
+}
+
+impl GraphicsCaptureApiHandler for Capture {
+    type Flags = SyncSender<(Vec<u8>, Size)>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn new(sender: Self::Flags) -> Result<Self, Self::Error> {
+        Ok(Self { sender })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        let size = Size { width: frame.width(), height: frame.height() };
+        let mut data = frame.buffer()?;
+
+        // Drop frames while the renderer is still busy with the previous one
+        match self.sender.try_send((data.as_raw_buffer().to_vec(), size)) {
+            Err(TrySendError::Disconnected(_)) => capture_control.stop(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // The sender is dropped with the handler, which ends the stream on the receiving side
+    fn on_closed(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Windows Graphics Capture of a single window
+pub struct WindowCapture {
+    receiver: Receiver<(Vec<u8>, Size)>,
+    size: Size,
+    _control: CaptureControl<Capture, Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl WindowCapture {
+    pub fn from_contains_name(window_title: &str) -> Result<Self, SourceError> {
+        let window = Window::from_contains_name(window_title)?;
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let settings = Settings::new(
+            window,
+            CursorCaptureSettings::Default,
+            DrawBorderSettings::Default,
+            settings::ColorFormat::Rgba8,
+            sender,
+        );
+        let control = Capture::start_free_threaded(settings)
+            .map_err(|error| format!("Failed to start capture: {}", error))?;
+
+        Ok(Self {
+            receiver,
+            size: Size::default(),
+            _control: control,
+        })
+    }
+}
+
+impl FrameSource for WindowCapture {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        match self.receiver.recv() {
+            Ok((buffer, size)) => {
+                self.size = size;
+                Ok(Some(buffer))
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Rgba8
+    }
+}
@@ -0,0 +1,219 @@
+use x11rb::connection::Connection;
+use x11rb::errors::ReplyError;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, ImageFormat, ImageOrder, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::frame::{ColorFormat, Size};
+use super::{FrameSource, Pacer, SourceError};
+
+// GetImage fails when the window is resized between the geometry query and the capture, or while it is unmapped
+const CAPTURE_ATTEMPTS: u32 = 5;
+
+/// X11 capture of a single window through GetImage
+pub struct X11Capture {
+    connection: RustConnection,
+    window: Window,
+    size: Size,
+    pacer: Pacer,
+}
+
+impl X11Capture {
+    // Same matching rules as `Window::from_contains_name` on Windows, first match wins
+    pub fn from_contains_name(window_title: &str, fps: f64) -> Result<Self, SourceError> {
+        Self::open(None, window_title, fps)
+    }
+
+    // Connects to `display`, or to $DISPLAY when it is `None`
+    fn open(display: Option<&str>, window_title: &str, fps: f64) -> Result<Self, SourceError> {
+        if window_title.is_empty() {
+            return Err("Window title must not be empty".into());
+        }
+
+        let (connection, screen_num) = x11rb::connect(display)?;
+        let setup = connection.setup();
+        let screen = &setup.roots[screen_num];
+        let supported = setup.pixmap_formats.iter()
+            .any(|format| format.depth == screen.root_depth && format.bits_per_pixel == 32);
+
+        if !supported || setup.image_byte_order != ImageOrder::LSB_FIRST {
+            return Err("Only 32 bit little endian X11 visuals are supported".into());
+        }
+
+        let root = screen.root;
+        let net_wm_name = connection.intern_atom(false, b"_NET_WM_NAME")?.reply()?.atom;
+        let utf8_string = connection.intern_atom(false, b"UTF8_STRING")?.reply()?.atom;
+        let names = (net_wm_name, utf8_string);
+        let mut window = None;
+
+        // The root window is never a capture target, start with its children
+        for child in connection.query_tree(root)?.reply()?.children {
+            window = find_window(&connection, child, window_title, names)?;
+
+            if window.is_some() {
+                break;
+            }
+        }
+
+        let window = window.ok_or("Failed to find window")?;
+
+        Ok(Self {
+            connection,
+            window,
+            size: Size::default(),
+            pacer: Pacer::new(fps),
+        })
+    }
+}
+
+fn window_name(connection: &RustConnection, window: Window, names: (Atom, Atom)) -> Result<String, SourceError> {
+    let (net_wm_name, utf8_string) = names;
+    let name = connection.get_property(false, window, net_wm_name, utf8_string, 0, u32::MAX)?.reply()?;
+
+    if !name.value.is_empty() {
+        return Ok(String::from_utf8_lossy(&name.value).into_owned());
+    }
+
+    let name = connection.get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, u32::MAX)?.reply()?;
+
+    Ok(String::from_utf8_lossy(&name.value).into_owned())
+}
+
+// Depth first search through the window tree
+fn find_window(
+    connection: &RustConnection,
+    window: Window,
+    window_title: &str,
+    names: (Atom, Atom),
+) -> Result<Option<Window>, SourceError> {
+    if window_name(connection, window, names)?.contains(window_title) {
+        return Ok(Some(window));
+    }
+
+    for child in connection.query_tree(window)?.reply()?.children {
+        if let Some(found) = find_window(connection, child, window_title, names)? {
+            return Ok(Some(found));
+        }
+    }
+
+    Ok(None)
+}
+
+impl FrameSource for X11Capture {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, SourceError> {
+        let mut attempts = 0;
+
+        loop {
+            self.pacer.wait();
+
+            let geometry = match self.connection.get_geometry(self.window)?.reply() {
+                Ok(geometry) => geometry,
+                // The window was destroyed, which ends the capture
+                Err(ReplyError::X11Error(_)) => return Ok(None),
+                Err(error) => return Err(error.into()),
+            };
+            let reply = self.connection
+                .get_image(ImageFormat::Z_PIXMAP, self.window, 0, 0, geometry.width, geometry.height, !0)?
+                .reply();
+
+            match reply {
+                Ok(image) => {
+                    self.size = Size { width: geometry.width as u32, height: geometry.height as u32 };
+
+                    return Ok(Some(image.data));
+                },
+                // Query the geometry again and retry on the next frame
+                Err(ReplyError::X11Error(_)) if attempts < CAPTURE_ATTEMPTS => attempts += 1,
+                Err(ReplyError::X11Error(error)) => {
+                    return Err(format!("Failed to capture window: {:?}", error.error_kind).into());
+                },
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    // 32 bit ZPixmap on a little endian server is laid out as B, G, R, X
+    fn color_format(&self) -> ColorFormat {
+        ColorFormat::Bgra8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command};
+    use std::{thread, time::Duration};
+    use x11rb::protocol::xproto::{ChangeGCAux, CreateGCAux, CreateWindowAux, PropMode, Rectangle, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    use crate::frame::FrameData;
+    use crate::pixel::Vector2;
+    use crate::vector2;
+    use super::*;
+
+    const DISPLAY: &str = ":97";
+
+    // Kills the server when the test ends, pass or fail
+    struct Xvfb(Child);
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb"]
+    fn captures_a_drawn_pattern() {
+        let _server = Xvfb(Command::new("Xvfb").args([DISPLAY, "-screen", "0", "320x240x24"]).spawn().unwrap());
+        let (connection, screen_num) = (0..50)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(100));
+                x11rb::connect(Some(DISPLAY)).ok()
+            })
+            .expect("Xvfb did not start");
+        let screen = &connection.setup().roots[screen_num];
+        let window = connection.generate_id().unwrap();
+        let gc = connection.generate_id().unwrap();
+        let aux = CreateWindowAux::new().background_pixel(screen.black_pixel);
+
+        connection.create_window(
+            COPY_DEPTH_FROM_PARENT, window, screen.root, 0, 0, 64, 32, 0, WindowClass::INPUT_OUTPUT, 0, &aux,
+        ).unwrap();
+        connection.change_property8(PropMode::REPLACE, window, AtomEnum::WM_NAME, AtomEnum::STRING, b"pattern client")
+            .unwrap();
+        connection.map_window(window).unwrap();
+
+        // Left half red, right half blue
+        connection.create_gc(gc, window, &CreateGCAux::new().foreground(0xff0000)).unwrap();
+        connection.poly_fill_rectangle(window, gc, &[Rectangle { x: 0, y: 0, width: 32, height: 32 }]).unwrap();
+        connection.change_gc(gc, &ChangeGCAux::new().foreground(0x0000ff)).unwrap();
+        connection.poly_fill_rectangle(window, gc, &[Rectangle { x: 32, y: 0, width: 32, height: 32 }]).unwrap();
+        connection.sync().unwrap();
+
+        assert!(X11Capture::open(Some(DISPLAY), "", 0.0).is_err());
+        assert!(X11Capture::open(Some(DISPLAY), "no such window", 0.0).is_err());
+
+        let mut capture = X11Capture::open(Some(DISPLAY), "pattern", 0.0).unwrap();
+        let buffer = capture.next_frame().unwrap().unwrap();
+        let mut frame_data = FrameData::default();
+
+        frame_data.set_color_format(capture.color_format());
+        frame_data.set_raw_data(buffer, capture.size());
+
+        let image = frame_data.get_image();
+
+        assert_eq!((capture.size().width, capture.size().height), (64, 32));
+        assert_eq!(image.get_pixel(vector2!(4, 4)).get_color_raw(), (255, 0, 0));
+        assert_eq!(image.get_pixel(vector2!(60, 28)).get_color_raw(), (0, 0, 255));
+
+        // An unmapped window can't be captured, that has to surface as an error
+        connection.unmap_window(window).unwrap();
+        connection.sync().unwrap();
+        assert!(capture.next_frame().is_err());
+    }
+}
@@ -1,5 +1,16 @@
-use std::{io, thread, time::Duration};
-use std::io::{Write, stdout};
+use std::env;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::frame::Size;
+
+// How long to wait for the terminal to answer the capability queries
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Image ids used for the kitty support queries, one per transmission medium
+const KITTY_DIRECT_ID: u32 = 31;
+const KITTY_SHARED_MEMORY_ID: u32 = 32;
+const KITTY_TEMP_FILE_ID: u32 = 33;
 
 pub struct Terminal {
     title: String
@@ -11,6 +22,500 @@ impl Terminal {
             title
         }
     }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
 //, x: usize, y: usize, color: Color
 
-}
\ No newline at end of file
+}
+
+// Windows consoles default to the OEM code page, which mangles the block characters
+#[cfg(windows)]
+pub fn enable_utf8() {
+    use winapi::um::{wincon::SetConsoleOutputCP, winnls::CP_UTF8};
+
+    unsafe { SetConsoleOutputCP(CP_UTF8) };
+}
+
+#[cfg(not(windows))]
+pub fn enable_utf8() {}
+
+// Turns on VT processing for the console, true when it takes escape sequences
+#[cfg(windows)]
+fn enable_virtual_terminal() -> bool {
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::{processenv::GetStdHandle, winbase::STD_OUTPUT_HANDLE, wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING};
+
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+
+        GetConsoleMode(handle, &mut mode) != 0
+            && (mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0)
+    }
+}
+
+#[cfg(not(windows))]
+fn enable_virtual_terminal() -> bool {
+    false
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum ColorDepth {
+    Monochrome,
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// How the kitty graphics protocol gets pixels to the terminal
+#[derive(Clone, Copy, PartialEq)]
+pub enum Medium {
+    // Pixels inline in the escape sequences
+    Direct,
+    // Temporary file the terminal reads and deletes
+    TempFile,
+    // POSIX shared memory object the terminal reads and unlinks
+    SharedMemory,
+}
+
+#[derive(Clone)]
+pub struct Capabilities {
+    pub color_depth: ColorDepth,
+    // Best transmission medium when the kitty graphics protocol is supported
+    pub kitty: Option<Medium>,
+    pub sixel: bool,
+    pub iterm: bool,
+    // Screen pixels per cell, from CSI 16 t
+    pub cell_pixels: Option<Size>,
+    // Parameters of the secondary device attributes reply
+    pub device_attributes: Vec<u32>,
+}
+
+/// Byte level access to a terminal, so the probe can run against something other than the real tty
+pub trait TermIo {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()>;
+    /// Returns whatever arrived within `timeout`, empty if nothing did
+    fn read_timeout(&mut self, timeout: Duration) -> io::Result<Vec<u8>>;
+    /// Raw mode hands replies over without waiting for a newline and keeps them from being echoed
+    fn set_raw_mode(&mut self, _raw: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays back canned replies once anything has been written, for probing without a terminal
+pub struct ScriptedTerminal {
+    pub replies: Vec<u8>,
+    pub written: Vec<u8>,
+}
+
+impl TermIo for ScriptedTerminal {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.written.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, _timeout: Duration) -> io::Result<Vec<u8>> {
+        match self.written.is_empty() {
+            true => Ok(Vec::new()),
+            false => Ok(std::mem::take(&mut self.replies)),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub struct Tty {
+    file: std::fs::File,
+}
+
+#[cfg(unix)]
+impl Tty {
+    pub fn open() -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+
+        Ok(Self { file })
+    }
+}
+
+#[cfg(unix)]
+impl TermIo for Tty {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+
+        self.file.write_all(data)?;
+        self.file.flush()
+    }
+
+    fn read_timeout(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let mut poll_fd = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+
+        if ready < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buffer = vec![0; 1024];
+        let read = match ready {
+            0 => 0,
+            _ => self.file.read(&mut buffer)?,
+        };
+
+        buffer.truncate(read);
+        Ok(buffer)
+    }
+
+    fn set_raw_mode(&mut self, raw: bool) -> io::Result<()> {
+        match raw {
+            true => crossterm::terminal::enable_raw_mode(),
+            false => crossterm::terminal::disable_raw_mode(),
+        }
+    }
+}
+
+// Stages pixels in a POSIX shared memory object for the kitty probe and renderer
+#[cfg(unix)]
+pub(crate) fn write_shared_memory(name: &str, data: &[u8]) -> io::Result<()> {
+    use std::ffi::CString;
+
+    let c_name = CString::new(name)?;
+
+    unsafe {
+        let fd = libc::shm_open(c_name.as_ptr(), libc::O_CREAT | libc::O_RDWR | libc::O_TRUNC, 0o600);
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if libc::ftruncate(fd, data.len() as libc::off_t) != 0 {
+            let error = io::Error::last_os_error();
+
+            libc::close(fd);
+            libc::shm_unlink(c_name.as_ptr());
+            return Err(error);
+        }
+
+        let memory = libc::mmap(
+            std::ptr::null_mut(),
+            data.len(),
+            libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+
+        libc::close(fd);
+
+        if memory == libc::MAP_FAILED {
+            libc::shm_unlink(c_name.as_ptr());
+            return Err(io::Error::last_os_error());
+        }
+
+        std::ptr::copy_nonoverlapping(data.as_ptr(), memory as *mut u8, data.len());
+        libc::munmap(memory, data.len());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_shared_memory(_name: &str, _data: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Shared memory needs a POSIX system"))
+}
+
+#[cfg(unix)]
+pub(crate) fn remove_shared_memory(name: &str) -> io::Result<()> {
+    let c_name = std::ffi::CString::new(name)?;
+
+    match unsafe { libc::shm_unlink(c_name.as_ptr()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn remove_shared_memory(_name: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Collects replies until `done` accepts them or `timeout` runs out
+pub fn read_replies(io: &mut dyn TermIo, timeout: Duration, done: impl Fn(&str) -> bool) -> io::Result<String> {
+    let start = Instant::now();
+    let mut replies = String::new();
+
+    while start.elapsed() < timeout {
+        let read = io.read_timeout(timeout.saturating_sub(start.elapsed()))?;
+
+        replies.push_str(&String::from_utf8_lossy(&read));
+
+        if done(&replies) {
+            break;
+        }
+    }
+
+    Ok(replies)
+}
+
+// Color depth and pixel protocols advertised through the environment alone
+fn capabilities_from_env(var: &dyn Fn(&str) -> Option<String>) -> Capabilities {
+    let term = var("TERM").unwrap_or_default();
+    let colorterm = var("COLORTERM").unwrap_or_default();
+    let term_program = var("TERM_PROGRAM").unwrap_or_default();
+    let color_depth = match () {
+        _ if colorterm == "truecolor" || colorterm == "24bit" => ColorDepth::TrueColor,
+        _ if term.contains("kitty") || term.contains("direct") => ColorDepth::TrueColor,
+        _ if term.contains("256color") => ColorDepth::Ansi256,
+        // Windows Terminal sets neither TERM nor COLORTERM
+        _ if term.is_empty() && var("WT_SESSION").is_some() => ColorDepth::TrueColor,
+        _ if term.is_empty() || term == "dumb" => ColorDepth::Monochrome,
+        _ => ColorDepth::Ansi16,
+    };
+    let kitty = match term.contains("kitty") || var("KITTY_WINDOW_ID").is_some() {
+        true => Some(Medium::Direct),
+        false => None,
+    };
+
+    Capabilities {
+        color_depth,
+        kitty,
+        sixel: false,
+        iterm: term_program == "iTerm.app" || term_program == "WezTerm",
+        cell_pixels: None,
+        device_attributes: Vec::new(),
+    }
+}
+
+// Parameters of every `intro ... final_byte` sequence in the replies
+fn find_sequences(replies: &str, intro: &str, final_byte: char) -> Vec<Vec<u32>> {
+    let mut sequences = Vec::new();
+    let mut rest = replies;
+
+    while let Some(start) = rest.find(intro) {
+        rest = &rest[start + intro.len()..];
+
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == ';')).unwrap_or(rest.len());
+
+        if rest[end..].starts_with(final_byte) {
+            sequences.push(rest[..end].split(';').filter_map(|param| param.parse().ok()).collect());
+        }
+    }
+
+    sequences
+}
+
+fn kitty_query(id: u32, medium: char, payload: &str) -> String {
+    format!("\x1b_Gi={},s=1,v=1,a=q,t={},f=24;{}\x1b\\", id, medium, payload)
+}
+
+fn kitty_probe_name() -> String {
+    format!("tty-graphics-protocol-terminal-render-probe-{}", std::process::id())
+}
+
+// Stages one pixel for each out of band kitty medium, returns the queries that test them
+fn kitty_medium_queries() -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use std::io::Write;
+
+    let mut queries = String::new();
+    let shared_memory = format!("/{}", kitty_probe_name());
+    let path = env::temp_dir().join(kitty_probe_name());
+
+    if write_shared_memory(&shared_memory, &[0, 0, 0]).is_ok() {
+        queries.push_str(&kitty_query(KITTY_SHARED_MEMORY_ID, 's', &STANDARD.encode(&shared_memory)));
+    }
+
+    if std::fs::File::create(&path).and_then(|mut file| file.write_all(&[0, 0, 0])).is_ok() {
+        queries.push_str(&kitty_query(KITTY_TEMP_FILE_ID, 't', &STANDARD.encode(path.to_string_lossy().as_bytes())));
+    }
+
+    queries
+}
+
+// Kitty removes what it read, anything left over belongs to a terminal that ignored the queries
+fn remove_kitty_probe_files() {
+    let _ = remove_shared_memory(&format!("/{}", kitty_probe_name()));
+    let _ = std::fs::remove_file(env::temp_dir().join(kitty_probe_name()));
+}
+
+/// Queries the terminal and falls back on the environment for anything it doesn't answer.
+/// Primary device attributes go last, every terminal answers those so they mark the end of the replies.
+pub fn probe(io: &mut dyn TermIo, var: &dyn Fn(&str) -> Option<String>) -> io::Result<Capabilities> {
+    let mut capabilities = capabilities_from_env(var);
+    let mut queries = kitty_query(KITTY_DIRECT_ID, 'd', "AAAA");
+
+    queries.push_str(&kitty_medium_queries());
+    // XTGETTCAP for RGB and Tc, the terminfo truecolor flags
+    queries.push_str("\x1bP+q524742\x1b\\\x1bP+q5463\x1b\\");
+    queries.push_str("\x1b[16t\x1b[>c\x1b[c");
+    let written = io.write_all(queries.as_bytes());
+
+    if written.is_err() {
+        remove_kitty_probe_files();
+    }
+
+    written?;
+
+    let replies = read_replies(io, PROBE_TIMEOUT, |replies| !find_sequences(replies, "\x1b[?", 'c').is_empty());
+
+    remove_kitty_probe_files();
+
+    let replies = replies?;
+
+    if let Some(attributes) = find_sequences(&replies, "\x1b[?", 'c').first() {
+        capabilities.sixel = attributes.contains(&4);
+    }
+
+    if let Some(attributes) = find_sequences(&replies, "\x1b[>", 'c').first() {
+        capabilities.device_attributes = attributes.clone();
+    }
+
+    if replies.contains("\x1bP1+r524742") || replies.contains("\x1bP1+r5463") {
+        capabilities.color_depth = ColorDepth::TrueColor;
+    }
+
+    let cell_size = find_sequences(&replies, "\x1b[6;", 't').into_iter()
+        .find(|params| params.len() == 2 && params[0] > 0 && params[1] > 0);
+
+    if let Some(params) = cell_size {
+        capabilities.cell_pixels = Some(Size { width: params[1], height: params[0] });
+    }
+
+    let kitty_ok = |id: u32| replies.contains(&format!("\x1b_Gi={};OK", id));
+
+    capabilities.kitty = match () {
+        _ if kitty_ok(KITTY_SHARED_MEMORY_ID) => Some(Medium::SharedMemory),
+        _ if kitty_ok(KITTY_TEMP_FILE_ID) => Some(Medium::TempFile),
+        _ if kitty_ok(KITTY_DIRECT_ID) => Some(Medium::Direct),
+        // The queries went unanswered, trust the environment
+        _ if replies.is_empty() => capabilities.kitty,
+        _ => None,
+    };
+
+    Ok(capabilities)
+}
+
+/// Probes the controlling terminal, or just the environment when there isn't one to talk to
+pub fn probe_terminal() -> Capabilities {
+    let var = |name: &str| env::var(name).ok();
+
+    #[cfg(unix)]
+    {
+        if let Ok(mut tty) = Tty::open() {
+            if tty.set_raw_mode(true).is_ok() {
+                let capabilities = probe(&mut tty, &var);
+
+                let _ = tty.set_raw_mode(false);
+
+                if let Ok(capabilities) = capabilities {
+                    return capabilities;
+                }
+            }
+        }
+    }
+
+    let mut capabilities = capabilities_from_env(&var);
+
+    // Neither does conhost, which draws 24-bit color as well once VT processing is on
+    if var("TERM").is_none() && capabilities.color_depth == ColorDepth::Monochrome && enable_virtual_terminal() {
+        capabilities.color_depth = ColorDepth::TrueColor;
+    }
+
+    capabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render;
+    use super::*;
+
+    fn probe_with(replies: &[u8], env: &[(&str, &str)]) -> (Capabilities, Vec<u8>) {
+        let mut terminal = ScriptedTerminal { replies: replies.to_vec(), written: Vec::new() };
+        let var = |name: &str| env.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string());
+        let capabilities = probe(&mut terminal, &var).unwrap();
+
+        (capabilities, terminal.written)
+    }
+
+    #[test]
+    fn primary_device_attributes_go_last() {
+        let (_, written) = probe_with(b"\x1b[?62;22c", &[]);
+
+        assert!(written.starts_with(b"\x1b_Gi=31,"));
+        assert!(written.ends_with(b"\x1b[>c\x1b[c"));
+    }
+
+    #[test]
+    fn windows_terminal_gets_truecolor() {
+        let (capabilities, _) = probe_with(b"\x1b[?61;6;7;22;23;24;28;32;42c", &[("WT_SESSION", "0c3f4a41-5d5e-4a38-b2a9-8e6c1f0d2b7a")]);
+
+        assert!(capabilities.color_depth == ColorDepth::TrueColor);
+        assert_eq!(render::select(&capabilities), "half");
+        assert!(capabilities_from_env(&|_| None).color_depth == ColorDepth::Monochrome);
+    }
+
+    #[test]
+    fn kitty_prefers_shared_memory() {
+        let replies = b"\x1b_Gi=31;OK\x1b\\\x1b_Gi=32;OK\x1b\\\x1b_Gi=33;OK\x1b\\\x1b[?62;22c";
+        let (capabilities, _) = probe_with(replies, &[("TERM", "xterm-kitty")]);
+
+        assert!(capabilities.kitty == Some(Medium::SharedMemory));
+        assert_eq!(render::select(&capabilities), "kitty");
+    }
+
+    #[test]
+    fn kitty_falls_back_to_the_mediums_it_accepts() {
+        let replies = b"\x1b_Gi=31;OK\x1b\\\x1b_Gi=32;ENOENT:no shm\x1b\\\x1b_Gi=33;OK\x1b\\\x1b[?62;22c";
+        let (capabilities, _) = probe_with(replies, &[]);
+
+        assert!(capabilities.kitty == Some(Medium::TempFile));
+        assert_eq!(render::select(&capabilities), "kitty");
+    }
+
+    #[test]
+    fn sixel_from_primary_device_attributes() {
+        // A kitty TERM that doesn't answer the graphics queries is a different terminal reusing the name
+        let (capabilities, _) = probe_with(b"\x1b[?62;4;22c\x1b[6;20;10t", &[("TERM", "xterm-kitty")]);
+
+        assert!(capabilities.sixel);
+        assert!(capabilities.kitty.is_none());
+        assert_eq!(capabilities.cell_pixels.map(|size| (size.width, size.height)), Some((10, 20)));
+        assert_eq!(render::select(&capabilities), "sixel");
+    }
+
+    #[test]
+    fn truecolor_from_colorterm_or_xtgettcap() {
+        let (capabilities, _) = probe_with(b"\x1b[?62;22c", &[("TERM", "xterm"), ("COLORTERM", "truecolor")]);
+
+        assert!(capabilities.color_depth == ColorDepth::TrueColor);
+        assert_eq!(render::select(&capabilities), "half");
+
+        let (capabilities, _) = probe_with(b"\x1bP1+r524742=31\x1b\\\x1b[?62;22c", &[("TERM", "xterm")]);
+
+        assert!(capabilities.color_depth == ColorDepth::TrueColor);
+
+        let (capabilities, _) = probe_with(b"\x1b[?62;22c", &[("TERM", "xterm-256color")]);
+
+        assert!(capabilities.color_depth == ColorDepth::Ansi256);
+    }
+
+    #[test]
+    fn silent_terminal_keeps_the_environment() {
+        let mut terminal = ScriptedTerminal { replies: Vec::new(), written: Vec::new() };
+        let start = Instant::now();
+        let var = |name: &str| (name == "TERM").then(|| "xterm-kitty".to_string());
+        let capabilities = probe(&mut terminal, &var).unwrap();
+
+        assert!(start.elapsed() >= PROBE_TIMEOUT);
+        assert!(capabilities.kitty == Some(Medium::Direct));
+        assert_eq!(render::select(&capabilities), "kitty");
+
+        let (capabilities, _) = probe_with(b"", &[("TERM", "dumb")]);
+
+        assert!(capabilities.color_depth == ColorDepth::Monochrome);
+        assert_eq!(render::select(&capabilities), "ascii");
+    }
+}